@@ -6,45 +6,75 @@ use dioxus::{
 };
 use dioxus_elements::input_data::MouseButton;
 use quantum::{
+    circuit::{circuit_stats, to_svg},
+    complex::significant,
+    examples::{GateNote, examples, gate_note},
     prelude::*,
-    qubit::{bloch_vector, partial_trace},
+    qubit::{DISPLAY_THRESHOLD, affected_basis_states},
+    simulator::StepOutcome,
 };
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 
+// The editor's view of a Simulator, adding drag and drop state and the Bloch sphere
 pub struct CircuitManager {
-    system: QubitSystem,
-    pub gates: Vec<Vec<Gate>>,
+    pub simulator: Simulator,
     current_drag: Gate,
+    moving_gate: Option<(usize, usize)>,
     dragging_wire: (bool, usize, usize),
+    pub bloch_scale: f64,
+    // Result of the latest step, shown as a toast when something was measured
+    pub last_outcome: Option<StepOutcome>,
+    // Largest circuit step will run, each step builds a 2^n by 2^n matrix
+    pub max_qubits: usize,
+    // Notes on the loaded example's gates, dropped from a cell once its gate is changed
+    pub notes: Vec<GateNote>,
+    // Cell of the gate under the mouse, whose amplitudes the heatmap highlights
+    pub hovered_gate: Option<(usize, usize)>,
+    // Significant digits of the amplitudes and angles shown, see significant
+    pub display_precision: usize,
+}
+
+// A circuit as saved to and loaded from a JSON file
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct SavedCircuit {
+    pub registers: usize,
+    pub gates: Vec<Vec<Gate>>,
     pub wires: Vec<(usize, usize, usize)>,
-    registers: usize,
-    pub step: usize,
-    functions: Vec<(String, Vec<Vec<Gate>>)>,
 }
 
+// Enough to tell amplitudes apart without the float noise of 0.7071067811865476
+pub const DEFAULT_DISPLAY_PRECISION: usize = 3;
+
+// Past this a step's full matrix is too big for the browser to allocate
+pub const DEFAULT_MAX_QUBITS: usize = 14;
+
 impl CircuitManager {
     pub fn new() -> Self {
         Self {
-            system: QubitSystem::new(vec![Qubit::zero()]),
-            gates: vec![vec![Gate::I]],
+            simulator: Simulator::new(1),
             current_drag: Gate::I,
+            moving_gate: None,
             dragging_wire: (false, 0, 0),
-            wires: Vec::new(),
-            registers: 1,
-            step: 0,
-            functions: Vec::new(),
+            bloch_scale: 8.0,
+            last_outcome: None,
+            max_qubits: DEFAULT_MAX_QUBITS,
+            notes: Vec::new(),
+            hovered_gate: None,
+            display_precision: DEFAULT_DISPLAY_PRECISION,
         }
     }
 
     pub fn gates_len(&self) -> usize {
-        self.gates.len()
+        self.simulator.gates_len()
     }
 
     pub fn registers_len(&self) -> usize {
-        self.registers
+        self.simulator.registers_len()
     }
 
     pub fn gates_range(&self, column: usize) -> Vec<usize> {
-        self.gates[column]
+        self.simulator.gates[column]
             .iter()
             .enumerate()
             .filter(|(_, gate)| **gate != Gate::Other(String::from("none")))
@@ -52,85 +82,125 @@ impl CircuitManager {
             .collect()
     }
 
-    pub fn add_register(&mut self) {
-        self.registers += 1;
-        for i in 0..self.gates.len() {
-            self.gates[i].push(Gate::I);
-        }
-        self.system.add_qubit(Qubit::zero());
-    }
-
-    pub fn edit_gate(&mut self, column: usize, register: usize, value: f64) {
-        self.gates[column][register] = match &self.gates[column][register] {
-            Gate::P(_) => Gate::P(value),
-            Gate::RX(_) => Gate::RX(value),
-            Gate::RY(_) => Gate::RY(value),
-            Gate::RZ(_) => Gate::RZ(value),
-            other => other.clone(),
-        }
+    pub fn set_example(
+        &mut self,
+        gates: Vec<Vec<Gate>>,
+        wires: Vec<(usize, usize, usize)>,
+        notes: Vec<GateNote>,
+    ) {
+        self.simulator.load(gates, wires);
+        self.notes = notes;
+        self.last_outcome = None;
     }
 
-    pub fn gate_value(&self, column: usize, register: usize) -> f64 {
-        match self.gates[column][register] {
-            Gate::P(n) => n,
-            Gate::RX(n) => n,
-            Gate::RY(n) => n,
-            Gate::RZ(n) => n,
-            _ => 0.0,
+    // Loads the bundled example with this exact name, returning false if there isn't one
+    pub fn load_example_by_name(&mut self, name: &str) -> bool {
+        match examples().into_iter().find(|example| example.0 == name) {
+            Some((_, gates, wires, notes)) => {
+                self.set_example(gates, wires, notes);
+                true
+            }
+            None => false,
         }
     }
 
-    pub fn get_values(&self) -> Vec<ComplexNumber> {
-        self.system.get_values()
-    }
-
-    pub fn set_example(&mut self, gates: Vec<Vec<Gate>>, wires: Vec<(usize, usize, usize)>) {
-        let len = gates[0].len();
-        self.registers = len;
-        self.system = QubitSystem::new(vec![Qubit::zero(); len]);
-        self.step = 0;
-        self.gates = gates;
-        self.wires = wires;
-    }
-
     pub fn handle_drop(&mut self, column: usize, register: usize) {
         if self.dragging_wire.0 {
-            if self.dragging_wire.1 == column && register != self.dragging_wire.2 {
-                self.wires.push((column, self.dragging_wire.2, register));
+            // Wires run from a measurement to a gate in the same column or any later one
+            let (_, start, from) = self.dragging_wire;
+            if column > start || (column == start && register != from) {
+                self.simulator.wires.push((column, self.dragging_wire.2, register));
             }
             return;
         }
 
-        let size = log2(self.current_drag.to_matrix().len());
-        if size > self.registers - register {
-            eval(&format!(
-                "alert(\"Quantum gate {:?} needs at least {} qubits to work.\")",
-                self.current_drag, size
+        if let Err(message) = self.drop_gate(column, register) {
+            alert(&message);
+        }
+    }
+
+    // Places the dragged or moved gate, or explains why it can't go there
+    fn drop_gate(&mut self, column: usize, register: usize) -> Result<(), String> {
+        let moving = self.moving_gate.take();
+        let gate = match moving {
+            Some(from) => self.simulator.gates[from.0][from.1].clone(),
+            None => self.current_drag.clone(),
+        };
+
+        // A moved gate can't get in its own way
+        let ignore = moving.filter(|from| from.0 == column).map(|from| from.1);
+        let size = self.simulator.gate_size(&gate);
+        let size_of = |gate: &Gate| self.simulator.gate_size(gate);
+        if let Some(other) =
+            overlapping_gate(&self.simulator.gates[column], register, size, ignore, size_of)
+        {
+            return Err(format!(
+                "Quantum gate {gate:?} would overlap the {:?} gate on qubit {}.",
+                self.simulator.gates[column][other],
+                other + 1
             ));
-            return;
         }
 
-        for i in (register + 1)..self.gates[column].len() {
-            if self.gates[column][i] == Gate::Other(String::from("none")) {
-                self.gates[column][i] = Gate::I;
-            } else {
-                break;
+        self.notes.retain(|note| (note.0, note.1) != (column, register));
+        let placed = match moving {
+            Some(from) => {
+                self.notes.retain(|note| (note.0, note.1) != from);
+                self.simulator.move_gate(from, (column, register))
             }
-        }
+            None => self.simulator.place_gate(column, register, gate.clone()),
+        };
 
-        self.gates[column][register] = self.current_drag.clone();
+        if !placed {
+            return Err(format!(
+                "Quantum gate {gate:?} needs at least {} qubits to work.",
+                self.simulator.gate_size(&gate)
+            ));
+        }
+        Ok(())
+    }
 
-        let mat_len = self.current_drag.to_matrix().len();
-        if mat_len > 2 {
-            for i in 1..(log2(mat_len)) {
-                self.gates[column][register + i] = Gate::Other(String::from("none"));
-            }
+    // Saves a circuit as a named gate, run on the qubits from wherever it's placed
+    // Trailing empty columns are dropped and wires aren't part of it
+    pub fn define_function(&mut self, name: String, gates: Vec<Vec<Gate>>) -> Result<(), String> {
+        let name = name.trim();
+        if name.is_empty() || name == "none" {
+            return Err(String::from("Give the gate a name first."));
+        }
+        if Gate::all_builtin().iter().any(|gate| format!("{gate:?}") == name) {
+            return Err(format!("{name} is already a built-in gate."));
         }
 
-        if column == self.gates.len() - 1 {
-            self.add_column();
+        let mut gates = gates;
+        while gates.last().is_some_and(|column| column.iter().all(Gate::is_identity)) {
+            gates.pop();
         }
-        // handle replacing big gates with smaller
+        if gates.is_empty() {
+            return Err(String::from("The circuit has no gates to save."));
+        }
+        if self.simulator.uses_function(&gates, name) {
+            return Err(format!("{name} can't be saved as a gate that uses itself."));
+        }
+
+        self.simulator.add_function(name, gates);
+        Ok(())
+    }
+
+    // Basis states the hovered gate would change if it were applied, see affected_basis_states
+    pub fn hovered_states(&self) -> Vec<usize> {
+        let Some((column, register)) = self.hovered_gate else {
+            return Vec::new();
+        };
+        let Some(gate) = self.simulator.gates.get(column).and_then(|column| column.get(register))
+        else {
+            return Vec::new();
+        };
+
+        let targets: Vec<usize> = (register..register + self.simulator.gate_size(gate)).collect();
+        affected_basis_states(gate, &targets, self.registers_len())
+    }
+
+    pub fn set_moving(&mut self, column: usize, register: usize) {
+        self.moving_gate = Some((column, register));
     }
 
     pub fn set_wire_drag(&mut self, dragging: bool, column: usize, register: usize) {
@@ -138,98 +208,177 @@ impl CircuitManager {
     }
 
     pub fn clear_system(&mut self) {
-        self.step = 0;
-        self.system = QubitSystem::new(vec![Qubit::zero(); 2]);
-        self.registers = 2;
-        self.gates = vec![vec![Gate::I; 2]];
-        self.wires = Vec::new();
-        Self::send_bloch_vectors(vec![vec![0.0, 0.0, 1.0]])
+        self.simulator.clear(2);
+        self.notes.clear();
+        self.last_outcome = None;
+        self.send_bloch_vectors(vec![vec![0.0, 0.0, 1.0]])
     }
 
     pub fn restart(&mut self) {
-        self.system = QubitSystem::new(vec![Qubit::zero(); self.registers]);
-        self.step = 0;
-        Self::send_bloch_vectors(vec![vec![0.0, 0.0, 1.0]])
+        self.simulator.restart();
+        self.last_outcome = None;
+        self.send_bloch_vectors(vec![vec![0.0, 0.0, 1.0]])
     }
 
-    pub fn send_bloch_vectors(bloch_vectors: Vec<Vec<f64>>) {
-        let js = eval(include_str!("../assets/blochupdate.js"));
-        let _ = js.send(
-            bloch_vectors
-                .into_iter()
-                .map(|v| vec![v[0] * 8.0, v[2] * 8.0, v[1] * 8.0])
-                .flatten()
-                .collect::<Vec<f64>>(),
-        );
+    // Starts every qubit in a random state and restarts, to show a circuit like
+    // teleportation works whatever its input
+    pub fn randomize_inputs(&mut self, rng: &mut impl Rng) {
+        let inputs = (0..self.registers_len()).map(|_| Qubit::random(rng)).collect();
+        self.simulator.set_inputs(inputs);
+        self.last_outcome = None;
     }
 
-    pub fn step(&mut self) {
-        if self.step == self.gates.len() {
-            return;
+    // Qubit count, depth and gate counts of the circuit as JSON, see circuit_stats
+    pub fn stats_json(&self) -> String {
+        serde_json::to_string(&circuit_stats(&self.simulator.gates)).unwrap()
+    }
+
+    // The circuit's gates, wires and qubit count as JSON, see SavedCircuit
+    pub fn to_json(&self) -> String {
+        let saved = SavedCircuit {
+            registers: self.registers_len(),
+            gates: self.simulator.gates.clone(),
+            wires: self.simulator.wires.clone(),
+        };
+        serde_json::to_string(&saved).unwrap()
+    }
+
+    // Loads a circuit saved by to_json, leaving the current one alone if it isn't valid
+    pub fn load_json(&mut self, s: &str) -> Result<(), String> {
+        let saved: SavedCircuit =
+            serde_json::from_str(s).map_err(|error| format!("Not a saved circuit: {error}"))?;
+
+        if saved.registers == 0
+            || saved.gates.is_empty()
+            || saved.gates.iter().any(|column| column.len() != saved.registers)
+        {
+            return Err(format!(
+                "Every column needs a gate for each of the {} qubits.",
+                saved.registers
+            ));
         }
-        self.step += 1;
-        let mut gates = self.gates[self.step - 1].clone();
-        let wires = self
-            .wires
-            .clone()
-            .into_iter()
-            .filter(|wire| wire.0 == self.step - 1)
-            .collect::<Vec<(usize, usize, usize)>>();
-
-        for i in 0..gates.len() {
-            if let Gate::Other(name) = gates[i].clone() {
-                self.apply_function(i, &name);
-            }
-
-            for wire in &wires {
-                if wire.2 == i {
-                    gates[i] = if self.system.measure_single(wire.1) == 1 {
-                        gates[i].clone()
-                    } else {
-                        Gate::I
-                    };
-                }
-            }
+        let out_of_range = |wire: &&(usize, usize, usize)| {
+            wire.0 >= saved.gates.len() || wire.1 >= saved.registers || wire.2 >= saved.registers
+        };
+        if let Some(wire) = saved.wires.iter().find(out_of_range) {
+            return Err(format!("The wire {wire:?} is outside the circuit."));
+        }
+        for (column, gates) in saved.gates.iter().enumerate() {
+            self.check_column(gates).map_err(|error| format!("Column {}: {error}", column + 1))?;
         }
 
-        self.system.apply_gates(gates);
+        self.set_example(saved.gates, saved.wires, Vec::new());
+        Ok(())
+    }
 
-        let mut density = self.system.density_matrix();
-        let mut bloch_vectors = Vec::new();
-        tracing::info!("{density:?}");
+    // Whether a loaded column could be stepped: every gate fits below its qubit with its
+    // cells below marked as continuations, and nothing else is marked as one
+    fn check_column(&self, gates: &[Gate]) -> Result<(), String> {
+        let none = Gate::Other(String::from("none"));
+        let mut register = 0;
 
-        for qubit_idx in 0..self.registers {
-            let mut density = density.clone();
-            let mut removed = 0;
-            let mut size = self.registers;
+        while register < gates.len() {
+            let gate = &gates[register];
+            if *gate == none {
+                return Err(format!("qubit {} continues a gate that isn't there.", register + 1));
+            }
+            check_gate(gate)?;
 
-            for i in 0..self.registers {
-                tracing::info!("{removed}, {qubit_idx}");
-                if i != qubit_idx {
-                    density = partial_trace(density.clone(), i - removed, size);
-                    size -= 1;
-                    removed += 1;
-                }
+            let size = self.simulator.gate_size(gate);
+            if register + size > gates.len() {
+                return Err(format!("{gate:?} on qubit {} doesn't fit.", register + 1));
+            }
+            if gates[register + 1..register + size].iter().any(|cell| *cell != none) {
+                return Err(format!("{gate:?} on qubit {} overlaps another gate.", register + 1));
             }
-            let b = bloch_vector(density.clone());
-            tracing::info!("Qubit {qubit_idx}: {b:?}");
-            bloch_vectors.push(b);
+            register += size;
         }
+        Ok(())
+    }
 
-        Self::send_bloch_vectors(bloch_vectors)
+    // Has the browser download the circuit as circuit.json, see to_json
+    pub fn download_json(&self) {
+        let literal = serde_json::to_string(&self.to_json()).unwrap();
+        eval(&format!(
+            "const link = document.createElement('a');
+             link.href = URL.createObjectURL(new Blob([{literal}], {{ type: 'application/json' }}));
+             link.download = 'circuit.json';
+             link.click();"
+        ));
     }
 
-    pub fn apply_function(&mut self, index: usize, name: &str) {}
+    // Puts a vector diagram of the circuit on the clipboard, for pasting into slides
+    pub fn copy_svg(&self) {
+        let simulator = &self.simulator;
+        copy_to_clipboard(&to_svg(&simulator.gates, &simulator.wires, simulator.registers_len()));
+    }
 
-    pub fn add_column(&mut self) {
-        self.gates.push(vec![Gate::I; self.registers]);
+    // Puts the current state vector on the clipboard, see state_json
+    pub fn copy_state(&self) {
+        copy_to_clipboard(&state_json(&self.simulator.get_values()));
+    }
+
+    pub fn send_bloch_vectors(&self, bloch_vectors: Vec<Vec<f64>>) {
+        let js = eval(include_str!("../assets/blochupdate.js"));
+        let _ = js.send(sphere_points(&bloch_vectors, self.bloch_scale));
+    }
+
+    // Runs the next column, returning false if nothing was run because the circuit has
+    // finished or is too big to step
+    pub fn step(&mut self) -> bool {
+        if !can_step(self.registers_len(), self.max_qubits) {
+            alert(&format!(
+                "Circuits with more than {} qubits are too large to step, try fewer qubits.",
+                self.max_qubits
+            ));
+            return false;
+        }
+
+        let Some(outcome) = self.simulator.step() else {
+            return false;
+        };
+        for (register, name) in &outcome.skipped_functions {
+            let width = self.simulator.function_width(name).unwrap_or(0);
+            alert(&skipped_function_message(*register, name, width));
+        }
+        self.last_outcome = Some(outcome);
+
+        let bloch_vectors = self.simulator.system().bloch_vectors();
+        tracing::info!("{bloch_vectors:?}");
+
+        self.send_bloch_vectors(bloch_vectors);
+        true
     }
 
     pub fn set_dragging(&mut self, gate: Gate) {
         self.current_drag = gate;
+        self.moving_gate = None;
+    }
+}
+
+// Catches gates whose parameters can't make a matrix, like a DiagonalPhase of 3 phases
+pub fn check_gate(gate: &Gate) -> Result<(), String> {
+    match gate {
+        Gate::DiagonalPhase(phases) if phases.len() < 2 || !phases.len().is_power_of_two() => {
+            Err(format!("a DiagonalPhase needs 2, 4, 8... phases, not {}.", phases.len()))
+        }
+        Gate::Controlled(inner) => check_gate(inner),
+        _ => Ok(()),
     }
 }
 
+// Whether a circuit with this many qubits is small enough to step
+pub fn can_step(registers: usize, max_qubits: usize) -> bool {
+    registers <= max_qubits
+}
+
+// Converts textbook [x, y, z] Bloch vectors into flattened points for the sphere renderer
+// three.js treats y as the vertical axis, so the z (|0⟩/|1⟩) axis is sent second and y last
+// Each component is multiplied by scale, the radius of the rendered sphere
+pub fn sphere_points(bloch_vectors: &[Vec<f64>], scale: f64) -> Vec<f64> {
+    bloch_vectors.iter().flat_map(|v| [v[0] * scale, v[2] * scale, v[1] * scale]).collect()
+}
+
 pub const CIRCUIT: GlobalSignal<CircuitManager> = Signal::global(CircuitManager::new);
 
 #[component]
@@ -242,7 +391,7 @@ pub fn CircuitEditor() -> Element {
                 class: "circuit",
                 div {
                     class: "registerstart",
-                    class: if CIRCUIT.read().step == 0 { "starthighlight" },
+                    class: if CIRCUIT.read().simulator.step == 0 { "starthighlight" },
                     for _ in 0..CIRCUIT.read().registers_len() {
                         div {
                             class: "qubitstart",
@@ -254,15 +403,15 @@ pub fn CircuitEditor() -> Element {
                 for i in 0..CIRCUIT.read().gates_len() {
                     div {
                         class: "gatecolumn",
-                        class: if CIRCUIT.read().step == i + 1 { "gatehighlight" },
+                        class: if CIRCUIT.read().simulator.step == i + 1 { "gatehighlight" },
                         for j in CIRCUIT.read().gates_range(i) {
                             GateObject { column: i, register: j }
                         }
-                        for j in 0..CIRCUIT.read().wires.len() {
-                            if CIRCUIT.read().wires[j].0 == i {
+                        for j in 0..CIRCUIT.read().simulator.wires.len() {
+                            if CIRCUIT.read().simulator.wires[j].0 == i {
                                 div {
                                     class: "wire",
-                                    style: "--wire-start: {CIRCUIT.read().wires[j].1}; --wire-end: {CIRCUIT.read().wires[j].2}"
+                                    style: "--wire-start: {CIRCUIT.read().simulator.wires[j].1}; --wire-end: {CIRCUIT.read().simulator.wires[j].2}"
                                 }
                             }
                         }
@@ -272,35 +421,261 @@ pub fn CircuitEditor() -> Element {
 
             button {
                 class: "addregister",
-                onclick: move |_| CIRCUIT.write().add_register(),
+                onclick: move |_| CIRCUIT.write().simulator.add_register(),
                 "Add Qubit"
             }
 
+            button {
+                class: "copystate",
+                onclick: move |_| CIRCUIT.read().copy_state(),
+                "Copy State"
+            }
+
+            button {
+                class: "copystate",
+                onclick: move |_| copy_to_clipboard(&CIRCUIT.read().stats_json()),
+                "Copy Stats"
+            }
+
+            button {
+                class: "copystate",
+                onclick: move |_| CIRCUIT.read().copy_svg(),
+                "Copy SVG"
+            }
+
+            label {
+                class: "precision",
+                "Digits "
+                input {
+                    r#type: "number",
+                    min: 1,
+                    max: 15,
+                    value: "{CIRCUIT.read().display_precision}",
+                    oninput: move |e| {
+                        if let Ok(precision) = e.data().value().parse::<usize>() {
+                            CIRCUIT.write().display_precision = precision.clamp(1, 15);
+                        }
+                    },
+                }
+            }
+
             div {
                 id: "systemvalues",
-                "{pretty_print(CIRCUIT.read().get_values())}"
+                "{pretty_print(&CIRCUIT.read().simulator.get_values(), DISPLAY_THRESHOLD, CIRCUIT.read().display_precision)}"
+            }
+
+            StateHeatmap {}
+            DensityGrid {}
+            StepHistory {}
+            MeasurementToast {}
+        }
+    }
+}
+
+#[component]
+pub fn MeasurementToast() -> Element {
+    let message = CIRCUIT.read().last_outcome.as_ref().map(measurement_message).unwrap_or_default();
+    let failed = CIRCUIT
+        .read()
+        .last_outcome
+        .as_ref()
+        .is_some_and(|outcome| outcome.assertions.iter().any(|(_, passed)| !passed));
+
+    rsx! {
+        if !message.is_empty() {
+            div {
+                class: "measurementtoast",
+                class: if failed { "toastfailed" },
+                "{message}"
+            }
+        }
+    }
+}
+
+// Register of a gate in the column whose qubits would overlap a gate of the given size
+// dropped at register. The gate already at register is replaced rather than overlapped,
+// and ignore skips a gate that's being moved out of the way. size_of gives how many cells
+// a gate already in the column covers, see Simulator::gate_size
+pub fn overlapping_gate(
+    column: &[Gate],
+    register: usize,
+    size: usize,
+    ignore: Option<usize>,
+    size_of: impl Fn(&Gate) -> usize,
+) -> Option<usize> {
+    for (other, gate) in column.iter().enumerate() {
+        if *gate == Gate::I || *gate == Gate::Other(String::from("none")) {
+            continue;
+        }
+
+        let other_size = size_of(gate);
+        if other != register
+            && Some(other) != ignore
+            && other < register + size
+            && register < other + other_size
+        {
+            return Some(other);
+        }
+    }
+    None
+}
+
+// Describes each measurement and assertion in a step, e.g. "Qubit 2 measured 1 (50% chance)"
+// Empty if nothing was measured or checked
+pub fn measurement_message(outcome: &StepOutcome) -> String {
+    let measurements = outcome.measurements.iter().map(|(qubit, result, probability)| {
+        format!("Qubit {} measured {} ({:.0}% chance)", qubit + 1, result, probability * 100.0)
+    });
+    let assertions = outcome.assertions.iter().map(|(qubit, passed)| {
+        format!("Assert on qubit {} {}", qubit + 1, if *passed { "passed" } else { "failed" })
+    });
+
+    measurements.chain(assertions).collect::<Vec<String>>().join(", ")
+}
+
+// Why a function gate was left as an identity, registers are numbered from 1 like the UI
+pub fn skipped_function_message(register: usize, name: &str, width: usize) -> String {
+    format!(
+        "{name} spans {width} qubits, too many to fit from qubit {}, so it was skipped",
+        register + 1
+    )
+}
+
+// Every amplitude as a colored square, readable for far more qubits than the ket list
+#[component]
+pub fn StateHeatmap() -> Element {
+    let values = CIRCUIT.read().simulator.get_values();
+    let probabilities = CIRCUIT.read().simulator.system().probabilities();
+    let registers = CIRCUIT.read().registers_len();
+    let affected = CIRCUIT.read().hovered_states();
+    let precision = CIRCUIT.read().display_precision;
+
+    rsx! {
+        div {
+            class: "stateheatmap",
+            for (idx, (value, probability)) in values.iter().zip(probabilities).enumerate() {
+                div {
+                    class: "heatmapcell",
+                    class: if affected.contains(&idx) { "heatmapaffected" },
+                    background: amplitude_color(probability, value.arg()),
+                    title: "|{idx_to_qubit(idx, registers)}⟩ {value.polar_precision(precision)}",
+                }
             }
         }
     }
 }
 
-pub fn pretty_print(qubit_values: Vec<ComplexNumber>) -> String {
+// Largest system whose density matrix is drawn, 2^6 x 2^6 cells is already a big grid
+const MAX_DENSITY_QUBITS: usize = 6;
+
+// The full density matrix as a grid, so coherences off the diagonal can be seen
+// Hidden behind a toggle since it grows with the square of the state vector
+#[component]
+pub fn DensityGrid() -> Element {
+    let mut shown = use_signal(|| false);
+    let registers = CIRCUIT.read().registers_len();
+
+    rsx! {
+        button {
+            class: "copystate",
+            onclick: move |_| shown.toggle(),
+            if shown() { "Hide Density Matrix" } else { "Show Density Matrix" }
+        }
+
+        if shown() && registers > MAX_DENSITY_QUBITS {
+            div { "Density matrix is only shown for up to {MAX_DENSITY_QUBITS} qubits" }
+        } else if shown() {
+            DensityCells { registers }
+        }
+    }
+}
+
+#[component]
+fn DensityCells(registers: usize) -> Element {
+    let density = CIRCUIT.read().simulator.system().density_matrix();
+    let size = density.len();
+    let precision = CIRCUIT.read().display_precision;
+
+    rsx! {
+        div {
+            class: "densitygrid",
+            style: "--density-size: {size}",
+            for i in 0..size {
+                for j in 0..size {
+                    div {
+                        class: "heatmapcell",
+                        background: amplitude_color(density[i][j].abs_squared(), density[i][j].arg()),
+                        title: "⟨{idx_to_qubit(i, registers)}|ρ|{idx_to_qubit(j, registers)}⟩ {density[i][j].polar_precision(precision)}",
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Color for an amplitude given its probability and phase in radians
+// Hue follows the phase (0 red, π/2 yellow-green, π cyan, -π/2 purple) and the cell
+// darkens from white towards a full color as the magnitude goes from 0 to 1
+pub fn amplitude_color(probability: f64, phase: f64) -> String {
+    let hue = phase.to_degrees().rem_euclid(360.0);
+    let lightness = 100.0 - 50.0 * probability.sqrt();
+    format!("hsl({hue:.0}, 80%, {lightness:.0}%)")
+}
+
+#[component]
+pub fn StepHistory() -> Element {
+    rsx! {
+        details {
+            class: "stephistory",
+            summary { "Step History" }
+            for (i, state) in CIRCUIT.read().simulator.history().iter().enumerate() {
+                div {
+                    class: "historystep",
+                    "Step {i + 1}: {state}"
+                }
+            }
+        }
+    }
+}
+
+// Shows a browser alert, quoting the message so names typed by the user can't break out of it
+pub fn alert(message: &str) {
+    let literal = serde_json::to_string(message).unwrap();
+    eval(&format!("alert({literal})"));
+}
+
+pub fn copy_to_clipboard(text: &str) {
+    let literal = serde_json::to_string(text).unwrap();
+    eval(&format!("navigator.clipboard.writeText({literal})"));
+}
+
+// State vector as a JSON array of {"re", "im"} objects, one per basis state
+pub fn state_json(values: &[ComplexNumber]) -> String {
+    serde_json::to_string(values).unwrap()
+}
+
+// The state as kets, leaving out basis states with a probability of threshold or less
+// Amplitudes are shown to precision significant digits
+pub fn pretty_print(qubit_values: &[ComplexNumber], threshold: f64, precision: usize) -> String {
     let mut ket_strings = Vec::new();
+    let registers = qubit_values.len().trailing_zeros() as usize;
 
     for (idx, value) in qubit_values.iter().enumerate() {
-        if *value == c!(0.0) {
+        if value.abs_squared() <= threshold {
             continue;
         }
-        ket_strings.push(format!("{} |{}⟩", value.pretty(), idx_to_qubit(idx)));
+        let ket = idx_to_qubit(idx, registers);
+        ket_strings.push(format!("{} |{ket}⟩", value.pretty_precision(precision)));
     }
 
     format!("{}", ket_strings.join(", "))
 }
 
-pub fn idx_to_qubit(idx: usize) -> String {
+// Ket label for a basis state index, qubit 0 first (see QubitSystem::index_of_basis)
+pub fn idx_to_qubit(idx: usize, registers: usize) -> String {
     let mut qubit = Vec::new();
 
-    for i in (0..CIRCUIT.read().registers).rev() {
+    for i in (0..registers).rev() {
         qubit.push(((idx >> i) & 1).to_string());
     }
 
@@ -314,9 +689,20 @@ pub fn GateObject(column: usize, register: usize) -> Element {
     rsx! {
         div {
             class: "quantumgate",
-            class: "gate{CIRCUIT.read().gates[column][register]:?}",
+            class: "{CIRCUIT.read().simulator.gates[column][register].css_class()}",
             id: "gate{column}_{register}",
+            // Saved circuits are as tall as the qubits they cover, built-in gates are sized in CSS
+            height: match &CIRCUIT.read().simulator.gates[column][register] {
+                Gate::Other(name) => CIRCUIT
+                    .read()
+                    .simulator
+                    .function_width(name)
+                    .map(|width| format!("{}px", 24 + 33 * (width.max(1) - 1))),
+                _ => None,
+            },
             border: if highlight() { "1px dotted black" },
+            draggable: CIRCUIT.read().simulator.gates[column][register] != Gate::I,
+            ondragstart: move |_| CIRCUIT.write().set_moving(column, register),
             ondragover: move |e| {
                 e.prevent_default();
                 highlight.set(true);
@@ -328,26 +714,59 @@ pub fn GateObject(column: usize, register: usize) -> Element {
 
                 CIRCUIT.write().handle_drop(column, register);
             },
+            onmouseenter: move |_| CIRCUIT.write().hovered_gate = Some((column, register)),
+            onmouseleave: move |_| CIRCUIT.write().hovered_gate = None,
             onmousedown: move |e| {
                 tracing::info!("{:?}", e.data());
                 if e.data().trigger_button().unwrap() == MouseButton::Auxiliary {
-                    CIRCUIT.write().gates[column][register] = Gate::I;
+                    CIRCUIT.write().simulator.remove_gate(column, register);
                 }
             },
-            "{CIRCUIT.read().gates[column][register]:?}"
-            if CIRCUIT.read().gates[column][register].is_variable() {
+            "{CIRCUIT.read().simulator.gates[column][register]:?}"
+            if let Some(note) = gate_note(&CIRCUIT.read().notes, column, register) {
+                span {
+                    class: "gatenote",
+                    title: "{note}",
+                    "{note}"
+                }
+            }
+            if CIRCUIT.read().simulator.gates[column][register].is_variable() {
+                "("
+                for (index, value) in CIRCUIT.read().simulator.gate_params(column, register).into_iter().enumerate() {
+                    if index > 0 {
+                        ", "
+                    }
+                    span {
+                        key: "{index}",
+                        contenteditable: true,
+                        oninput: move |e| {
+                            CIRCUIT.write().simulator.edit_gate(column, register, index, e.data().value().parse().unwrap_or(0.0));
+                        },
+                        role: "textbox",
+                        {significant(value, CIRCUIT.read().display_precision)}
+                    }
+                }
+                ")"
+            }
+            if let Gate::Assert(ket) = CIRCUIT.read().simulator.gates[column][register].clone() {
                 "("
                 span {
                     contenteditable: true,
-                    oninput: move |e| {
-                        CIRCUIT.write().edit_gate(column, register, e.data().value().parse().unwrap_or(0.0));
-                    },
+                    oninput: move |e| CIRCUIT.write().simulator.edit_assert(column, register, &e.data().value()),
                     role: "textbox",
-                    {CIRCUIT.read().gate_value(column, register).to_string()}
+                    {ket}
                 }
                 ")"
             }
-            if CIRCUIT.read().gates[column][register] == Gate::M {
+            if let Gate::Init(one) = CIRCUIT.read().simulator.gates[column][register] {
+                span {
+                    class: "initvalue",
+                    onclick: move |_| CIRCUIT.write().simulator.toggle_init(column, register),
+                    title: "Click to switch between |0⟩ and |1⟩",
+                    if one { "|1⟩" } else { "|0⟩" }
+                }
+            }
+            if matches!(CIRCUIT.read().simulator.gates[column][register], Gate::M | Gate::MeasureReset) {
                 WireCreator { column, register }
             }
         }
@@ -360,6 +779,7 @@ pub fn WireCreator(column: usize, register: usize) -> Element {
         div {
             class: "wirecreator",
             draggable: true,
+            ondragstart: move |e| e.stop_propagation(),
             ondrag: move |e| {
                 e.prevent_default();
                 CIRCUIT.write().set_wire_drag(true, column, register);
@@ -372,89 +792,18 @@ pub fn WireCreator(column: usize, register: usize) -> Element {
     }
 }
 
-#[macro_export]
-macro_rules! gates {
-    // Match the pattern for a 2D matrix
-    [$([$($elem:expr),* $(,)?]),* $(,)?] => {
-        {
-            use Gate::*;
-            vec![
-                $(
-                    vec![$($elem),*]
-                ),*
-            ]
-        }
-    };
-}
-
 #[component]
 pub fn CircuitParts() -> Element {
+    // Every built-in gate but the identity, which is what an empty cell already is
     let gates = use_signal(|| {
-        vec![
-            Gate::X,
-            Gate::Y,
-            Gate::Z,
-            Gate::H,
-            Gate::M,
-            Gate::S,
-            Gate::P(0.0),
-            Gate::RX(0.0),
-            Gate::RY(0.0),
-            Gate::RZ(0.0),
-            Gate::CNOT,
-            Gate::CZ,
-            Gate::SWAP,
-            Gate::CCX,
-            Gate::CCCX,
-            Gate::CSWAP,
-        ]
+        Gate::all_builtin().into_iter().filter(|gate| *gate != Gate::I).collect::<Vec<Gate>>()
     });
 
-    let examples = use_signal(|| {
-        vec![
-            ("Entanglement", gates![[H, I], [CNOT, Other(String::from("none"))], [I, I]], vec![]),
-            (
-                "Partial Entanglement",
-                gates![
-                    [H, I],
-                    [I, RY(0.39269)],
-                    [CNOT, Other(String::from("none"))],
-                    [I, RY(0.39269)],
-                    [I, I]
-                ],
-                vec![],
-            ),
-            (
-                "Quantum Teleportation",
-                gates![
-                    [X, H, I], 
-                    [I, CNOT, Other(String::from("none"))], 
-                    [CNOT, Other(String::from("none")), I],
-                    [H, I, I],
-                    [I, M, X],
-                    [M, I, Z],
-                    [I, I, I]
-                ],
-                vec![(4, 1, 2), (5, 0, 2)],
-            ),
-            (
-                "Deutsch–Jozsa Algorithm",
-                gates![
-                    [X, I, I],
-                    [H, H, H],
-                    [SWAP, Other(String::from("none")), I],
-                    [I, CNOT, Other(String::from("none"))],
-                    [SWAP, Other(String::from("none")), I],
-                    [I, CNOT, Other(String::from("none"))],
-                    [H, H, I],
-                    [M, M, I]
-                ],
-                vec![]
-            ),
-        ]
-    });
+    let examples = use_signal(examples);
+    let mut function_name = use_signal(String::new);
 
     let mut dragging = use_signal(|| false);
+    let mut player = use_signal(|| None::<Task>);
 
     rsx! {
         div {
@@ -480,8 +829,69 @@ pub fn CircuitParts() -> Element {
                 },
             }
 
+            for (name, _) in CIRCUIT.read().simulator.functions().to_vec() {
+                div {
+                    class: "gatedrag",
+                    draggable: true,
+                    border: "1px solid black",
+                    ondrag: move |_| CIRCUIT.write().set_dragging(Gate::Other(name.clone())),
+                    "{name}"
+                }
+            }
+
+            input {
+                class: "functionname",
+                placeholder: "Gate name",
+                value: "{function_name}",
+                oninput: move |e| function_name.set(e.data().value()),
+            }
+
+            button {
+                class: "savefunctionbutton",
+                onclick: move |_| {
+                    let mut circuit = CIRCUIT.write();
+                    let gates = circuit.simulator.gates.clone();
+                    match circuit.define_function(function_name(), gates) {
+                        Ok(()) => function_name.set(String::new()),
+                        Err(message) => {
+                            alert(&message);
+                        }
+                    }
+                },
+                "Save as Gate"
+            }
+
             div { flex_grow: 1 }
 
+            button {
+                class: "savebutton",
+                onclick: move |_| CIRCUIT.read().download_json(),
+                "Save Circuit"
+            }
+
+            label {
+                class: "loadbutton",
+                "Load Circuit"
+                input {
+                    r#type: "file",
+                    accept: ".json",
+                    display: "none",
+                    onchange: move |e| async move {
+                        let Some(files) = e.files() else {
+                            return;
+                        };
+                        for name in files.files() {
+                            let Some(contents) = files.read_file_to_string(&name).await else {
+                                continue;
+                            };
+                            if let Err(message) = CIRCUIT.write().load_json(&contents) {
+                                alert(&message);
+                            }
+                        }
+                    },
+                }
+            }
+
             select {
                 class: "exampleselector",
                 option {
@@ -489,7 +899,9 @@ pub fn CircuitParts() -> Element {
                 }
                 for i in 0..examples.read().len() {
                     option {
-                        onclick: move |_| CIRCUIT.write().set_example(examples.read()[i].1.clone(), examples.read()[i].2.clone()),
+                        onclick: move |_| {
+                            CIRCUIT.write().load_example_by_name(examples.read()[i].0);
+                        },
                         "{examples.read()[i].0}"
                     }
                 }
@@ -497,42 +909,76 @@ pub fn CircuitParts() -> Element {
 
             // button {
             //     class: "addgatebutton",
-            //     onclick: move |_| CIRCUIT.write().add_column(),
+            //     onclick: move |_| CIRCUIT.write().simulator.add_column(),
             //     "+"
             // }
 
             button {
                 class: "clearbutton",
-                onclick: move |_| CIRCUIT.write().clear_system(),
+                onclick: move |_| {
+                    if let Some(task) = player.take() {
+                        task.cancel();
+                    }
+                    CIRCUIT.write().clear_system();
+                },
                 "Clear System"
             }
 
             button {
                 class: "resetbutton",
-                onclick: move |_| CIRCUIT.write().restart(),
+                onclick: move |_| {
+                    if let Some(task) = player.take() {
+                        task.cancel();
+                    }
+                    CIRCUIT.write().restart();
+                },
                 "Restart Simulation"
             }
 
+            button {
+                class: "resetbutton",
+                onclick: move |_| {
+                    if let Some(task) = player.take() {
+                        task.cancel();
+                    }
+                    let mut circuit = CIRCUIT.write();
+                    circuit.randomize_inputs(&mut rand::thread_rng());
+                    circuit.send_bloch_vectors(circuit.simulator.system().bloch_vectors());
+                },
+                "Randomize Inputs"
+            }
+
+            button {
+                class: "playbutton",
+                onclick: move |_| match player.take() {
+                    Some(task) => task.cancel(),
+                    None => player.set(Some(spawn(play_circuit(player)))),
+                },
+                if player.read().is_some() { "Pause" } else { "Play" }
+            }
+
             button {
                 class: "stepbutton",
-                onclick: move |_| CIRCUIT.write().step(),
+                onclick: move |_| {
+                    CIRCUIT.write().step();
+                },
                 "Simulation Step"
             }
         }
     }
 }
 
-// Cheap log2 to use for my matrices
-pub fn log2(n: usize) -> usize {
-    match n {
-        1 => 0,
-        2 => 1,
-        4 => 2,
-        8 => 3,
-        16 => 4,
-        32 => 5,
-        _ => panic!("Incorrectly sized matrix used."),
+// Steps through the rest of the circuit once a second, until it ends, a step can't be run
+// or the task is cancelled
+async fn play_circuit(mut player: Signal<Option<Task>>) {
+    while CIRCUIT.read().simulator.has_next_step() {
+        if !CIRCUIT.write().step() {
+            break;
+        }
+        let _ = eval("await new Promise(resolve => setTimeout(resolve, 1000)); return null;").await;
     }
+
+    player.set(None);
 }
 
 pub fn gate_info(gate: &Gate) -> &str {
@@ -543,17 +989,286 @@ pub fn gate_info(gate: &Gate) -> &str {
         Gate::Z => "Flips on the Z axis",
         Gate::H => "Puts a qubit into a superposition",
         Gate::M => "Measures a qubit",
+        Gate::MeasureReset => "Measures a qubit, then resets it to |0⟩",
         Gate::P(_) => "Alters the phase of a qubit",
         Gate::S => "Phase shift of pi/2",
+        Gate::Sdg => "Phase shift of -π/2",
+        Gate::T => "Phase shift of π/4",
+        Gate::Tdg => "Phase shift of -π/4",
         Gate::RX(_) => "Rotates the X axis",
         Gate::RY(_) => "Rotates the Y axis",
         Gate::RZ(_) => "Rotates the Z axis",
+        Gate::U3(..) => "Any single qubit rotation, given as three angles",
+        Gate::Rot(..) => "Rotates about an axis (x, y, z) by an angle",
         Gate::CNOT => "Performs an X gate depending on another qubit",
+        Gate::ACNOT => "Performs X when the control is 0",
         Gate::CZ => "Performs a Z gate depending on another qubit",
+        Gate::CP(_) => "Performs a phase gate depending on another qubit",
         Gate::SWAP => "Swaps two qubits",
         Gate::CCX => "Performs an X gate depending on two qubits",
         Gate::CCCX => "Performs an X gate depending on three qubits",
         Gate::CSWAP => "Performs a swap depending on a qubit",
-        Gate::Other(_) => "Nothing",
+        Gate::CCZ => "Performs a Z gate depending on two qubits",
+        Gate::Controlled(_) => "Performs the inner gate depending on the qubit above it",
+        Gate::DiagonalPhase(_) => "Shifts the phase of each basis state by its own angle",
+        Gate::Init(_) => "Initializes a fresh qubit to |0⟩ or |1⟩",
+        Gate::Assert(_) => "Checks the state is the given ket",
+        Gate::Other(name) if name == "none" => "Nothing",
+        Gate::Other(_) => "A saved circuit, run on the qubits it covers",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use quantum::{circuit::run_circuit, gates};
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use super::*;
+
+    #[test]
+    pub fn step_guard() {
+        assert!(can_step(3, DEFAULT_MAX_QUBITS));
+        assert!(can_step(DEFAULT_MAX_QUBITS, DEFAULT_MAX_QUBITS));
+        assert!(!can_step(DEFAULT_MAX_QUBITS + 1, DEFAULT_MAX_QUBITS));
+        assert!(!can_step(20, DEFAULT_MAX_QUBITS));
+    }
+
+    #[test]
+    pub fn overlapping_drops() {
+        let none = || Gate::Other(String::from("none"));
+        let column = vec![Gate::CNOT, none(), Gate::X, Gate::I];
+        let size_of = |gate: &Gate| gate.num_qubits().max(1);
+
+        assert_eq!(overlapping_gate(&column, 1, 2, None, size_of), Some(0));
+        assert_eq!(overlapping_gate(&column, 1, 1, None, size_of), Some(0));
+        assert_eq!(overlapping_gate(&column, 0, 2, None, size_of), None);
+        assert_eq!(overlapping_gate(&column, 3, 1, None, size_of), None);
+        assert_eq!(overlapping_gate(&column, 2, 2, None, size_of), None);
+        assert_eq!(overlapping_gate(&column, 0, 3, None, size_of), Some(2));
+        assert_eq!(overlapping_gate(&column, 1, 2, Some(0), size_of), Some(2));
+        assert_eq!(overlapping_gate(&column, 1, 1, Some(0), size_of), None);
+
+        let mut circuit = CircuitManager::new();
+        circuit.simulator.add_register();
+        circuit.simulator.add_register();
+        circuit.simulator.place_gate(0, 0, Gate::CNOT);
+        let original = circuit.simulator.gates[0].clone();
+
+        circuit.current_drag = Gate::CNOT;
+        assert!(circuit.drop_gate(0, 1).is_err());
+        assert_eq!(circuit.simulator.gates[0], original);
+        assert!(circuit.drop_gate(0, 0).is_ok());
+    }
+
+    #[test]
+    pub fn hovered_amplitudes() {
+        let mut circuit = CircuitManager::new();
+        circuit.simulator.add_register();
+        circuit.simulator.place_gate(0, 0, Gate::CNOT);
+        assert!(circuit.hovered_states().is_empty());
+
+        circuit.hovered_gate = Some((0, 0));
+        assert_eq!(circuit.hovered_states(), vec![2, 3]);
+        circuit.hovered_gate = Some((5, 0));
+        assert!(circuit.hovered_states().is_empty());
+    }
+
+    #[test]
+    pub fn json_round_trip() {
+        let mut circuit = CircuitManager::new();
+        assert!(circuit.load_example_by_name("Quantum Teleportation"));
+        let gates = circuit.simulator.gates.clone();
+        let wires = circuit.simulator.wires.clone();
+        let json = circuit.to_json();
+
+        let mut loaded = CircuitManager::new();
+        assert!(loaded.load_json(&json).is_ok());
+        assert_eq!(loaded.simulator.gates, gates);
+        assert_eq!(loaded.simulator.wires, wires);
+        assert_eq!(loaded.registers_len(), circuit.registers_len());
+
+        // Angles come back exactly, and broken files leave the circuit as it was
+        loaded.simulator.place_gate(0, 0, Gate::RX(0.1 + 0.2));
+        let json = loaded.to_json();
+        assert!(circuit.load_json(&json).is_ok());
+        assert_eq!(circuit.simulator.gates[0][0], Gate::RX(0.1 + 0.2));
+        assert!(circuit.load_json("{}").is_err());
+        assert!(circuit.load_json(r#"{"registers":2,"gates":[["H"]],"wires":[]}"#).is_err());
+        // A CNOT on the last qubit, a bad DiagonalPhase, a gate on another's continuation cell
+        // and a continuation cell of nothing would all panic when stepped
+        for gates in [
+            r#"[["H","CNOT"]]"#,
+            r#"[[{"DiagonalPhase":[0.0,1.0,2.0]},"I"]]"#,
+            r#"[["CNOT","H"]]"#,
+            r#"[[{"Other":"none"},"H"]]"#,
+        ] {
+            let json = format!(r#"{{"registers":2,"gates":{gates},"wires":[]}}"#);
+            assert!(circuit.load_json(&json).is_err(), "{gates} loaded");
+        }
+        let cnot = r#"{"registers":2,"gates":[["CNOT",{"Other":"none"}]],"wires":[]}"#;
+        assert!(circuit.load_json(cnot).is_ok());
+        circuit.load_json(&json).unwrap();
+        assert_eq!(circuit.simulator.gates, loaded.simulator.gates);
+    }
+
+    #[test]
+    pub fn saved_functions() {
+        let mut circuit = CircuitManager::new();
+        circuit.simulator.add_register();
+        circuit.simulator.add_register();
+
+        let bell = gates![[H, I], [CNOT, Other(String::from("none"))]];
+        assert!(circuit.define_function(String::from(" "), bell.clone()).is_err());
+        assert!(circuit.define_function(String::from("CNOT"), bell.clone()).is_err());
+        let mut padded = bell.clone();
+        padded.push(vec![Gate::I, Gate::I]);
+        assert!(circuit.define_function(String::from("bell"), padded).is_ok());
+        assert_eq!(circuit.simulator.functions()[0].1, bell);
+        let nested = gates![[Other(String::from("bell")), Other(String::from("none"))]];
+        assert!(circuit.define_function(String::from("bell"), nested.clone()).is_err());
+        assert!(circuit.define_function(String::from("outer"), nested).is_ok());
+        let outer = gates![[Other(String::from("outer")), Other(String::from("none"))]];
+        assert!(circuit.define_function(String::from("bell"), outer).is_err());
+
+        // The saved gate covers both qubits it acts on, so it can't start on the last one
+        circuit.current_drag = Gate::Other(String::from("bell"));
+        assert!(circuit.drop_gate(0, 2).is_err());
+        assert!(circuit.drop_gate(0, 1).is_ok());
+        let none = Gate::Other(String::from("none"));
+        assert_eq!(
+            circuit.simulator.gates[0],
+            [Gate::I, circuit.current_drag.clone(), none.clone()]
+        );
+
+        // Nothing else can go on its second cell, though the gate alone doesn't know its size
+        circuit.current_drag = Gate::H;
+        assert!(circuit.drop_gate(0, 2).is_err());
+        assert_eq!(circuit.simulator.gates[0][2], none);
+
+        while circuit.simulator.step().is_some() {}
+        let inlined = run_circuit(&gates![[I, H, I], [I, CNOT, Other(String::from("none"))]], &[]);
+        assert!(circuit.simulator.system().fidelity(&inlined) > 1.0 - 1e-9);
+
+        // Sharing its column with another gate, the whole saved gate still runs as one
+        let column = gates![[Other(String::from("bell")), Other(String::from("none")), X]];
+        circuit.set_example(column, Vec::new(), Vec::new());
+        while circuit.simulator.step().is_some() {}
+        let inlined = run_circuit(&gates![[H, I, X], [CNOT, Other(String::from("none")), I]], &[]);
+        assert!(circuit.simulator.system().fidelity(&inlined) > 1.0 - 1e-9);
+    }
+
+    #[test]
+    pub fn random_inputs() {
+        let mut circuit = CircuitManager::new();
+        assert!(circuit.load_example_by_name("Quantum Teleportation"));
+        circuit.simulator.step();
+
+        circuit.randomize_inputs(&mut StdRng::seed_from_u64(1526));
+        let system = circuit.simulator.system();
+        assert_eq!(circuit.simulator.step, 0);
+        assert_eq!(system.num_qubits(), 3);
+        assert!((system.probabilities().iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        assert!(circuit.simulator.inputs().iter().all(Qubit::is_normal));
+    }
+
+    #[test]
+    pub fn small_amplitudes() {
+        let values = vec![c!(0.04), c!((1.0f64 - 0.04 * 0.04).sqrt()), c!(0.0), c!(0.0)];
+
+        let precision = DEFAULT_DISPLAY_PRECISION;
+        assert!(pretty_print(&values, DISPLAY_THRESHOLD, precision).contains("|00⟩"));
+        assert!(!pretty_print(&values, 0.01, precision).contains("|00⟩"));
+        assert!(pretty_print(&values, 0.01, precision).contains("|01⟩"));
+        assert!(!pretty_print(&values, DISPLAY_THRESHOLD, precision).contains("|10⟩"));
+
+        let half = ComplexNumber::SQRT_HALF;
+        let bell = vec![half, c!(0.0), c!(0.0), half];
+        assert_eq!(pretty_print(&bell, DISPLAY_THRESHOLD, 3), "0.707 |00⟩, 0.707 |11⟩");
+        assert_eq!(pretty_print(&bell, DISPLAY_THRESHOLD, 5), "0.70711 |00⟩, 0.70711 |11⟩");
+    }
+
+    #[test]
+    pub fn sphere_mapping() {
+        let points = sphere_points(&[vec![1.0, 2.0, 3.0], vec![0.0, 0.0, -1.0]], 8.0);
+        assert_eq!(points, vec![8.0, 24.0, 16.0, 0.0, -8.0, 0.0]);
+
+        let points = sphere_points(&[vec![1.0, 2.0, 3.0]], 1.0);
+        assert_eq!(points, vec![1.0, 3.0, 2.0]);
+    }
+
+    #[test]
+    pub fn bell_json() {
+        let half = ComplexNumber::SQRT_HALF;
+        let json = state_json(&[half, c!(0.0), c!(0.0), half]);
+        assert_eq!(
+            json,
+            r#"[{"re":0.7071067811865475,"im":0.0},{"re":0.0,"im":0.0},{"re":0.0,"im":0.0},{"re":0.7071067811865475,"im":0.0}]"#
+        );
+    }
+
+    #[test]
+    pub fn measurement_toast() {
+        let outcome = StepOutcome {
+            column: 4,
+            measurements: vec![(1, 0, 0.5), (2, 1, 1.0)],
+            assertions: vec![(0, false)],
+            skipped_functions: vec![],
+        };
+        assert_eq!(
+            measurement_message(&outcome),
+            "Qubit 2 measured 0 (50% chance), Qubit 3 measured 1 (100% chance), \
+             Assert on qubit 1 failed"
+        );
+
+        let empty = StepOutcome {
+            column: 0,
+            measurements: vec![],
+            assertions: vec![],
+            skipped_functions: vec![],
+        };
+        assert!(measurement_message(&empty).is_empty());
+        assert_eq!(
+            skipped_function_message(2, "adder", 4),
+            "adder spans 4 qubits, too many to fit from qubit 3, so it was skipped"
+        );
+    }
+
+    #[test]
+    pub fn heatmap_colors() {
+        assert_eq!(amplitude_color(0.0, 0.0), "hsl(0, 80%, 100%)");
+        assert_eq!(amplitude_color(1.0, 0.0), "hsl(0, 80%, 50%)");
+        assert_eq!(amplitude_color(0.5, std::f64::consts::PI), "hsl(180, 80%, 65%)");
+        assert_eq!(amplitude_color(0.25, -std::f64::consts::FRAC_PI_2), "hsl(270, 80%, 75%)");
+
+        let i = c!(0.0, 1.0);
+        assert_eq!(amplitude_color(i.abs_squared(), i.arg()), "hsl(90, 80%, 50%)");
+    }
+
+    #[test]
+    pub fn example_by_name() {
+        let mut circuit = CircuitManager::new();
+        assert!(circuit.load_example_by_name("Quantum Teleportation"));
+        assert_eq!(circuit.registers_len(), 3);
+
+        assert!(!circuit.load_example_by_name("teleportation"));
+        assert_eq!(circuit.registers_len(), 3);
+    }
+
+    #[test]
+    pub fn teleportation_stats() {
+        let mut circuit = CircuitManager::new();
+        circuit.load_example_by_name("Quantum Teleportation");
+
+        assert_eq!(
+            circuit.stats_json(),
+            r#"{"qubits":3,"depth":6,"gate_counts":{"CNOT":2,"H":2,"M":2,"X":2,"Z":1},"t_count":0,"measurements":2}"#
+        );
+    }
+
+    #[test]
+    pub fn phase_gate_info() {
+        for gate in [Gate::Sdg, Gate::T, Gate::Tdg] {
+            assert!(!gate_info(&gate).is_empty(), "{gate:?}");
+        }
     }
 }
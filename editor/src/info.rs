@@ -1,4 +1,5 @@
 use dioxus::prelude::*;
+use quantum::{circuit::lint_circuit, prelude::*};
 use crate::circuit::CIRCUIT;
 
 #[component]
@@ -40,6 +41,7 @@ pub fn Info() -> Element {
             class: "infosection",
             div {
                 class: "qubitinfo",
+                CircuitWarnings {}
                 h3 { "Getting Started" }
                 p {
                     {getting_started}
@@ -52,6 +54,7 @@ pub fn Info() -> Element {
                 p {
                     {entanglement}
                 }
+                QubitPurities {}
                 h3 { "More Reading" }
                 a {
                     href: "https://learn.microsoft.com/en-us/azure/quantum/concepts-the-qubit",
@@ -81,6 +84,48 @@ pub fn Info() -> Element {
     }
 }
 
+#[component]
+pub fn CircuitWarnings() -> Element {
+    let warnings = lint_circuit(&CIRCUIT.read().simulator.gates);
+
+    rsx! {
+        if !warnings.is_empty() {
+            h3 { "Circuit Warnings" }
+            ul {
+                class: "circuitwarnings",
+                for warning in warnings {
+                    li { "{warning}" }
+                }
+            }
+        }
+    }
+}
+
+#[component]
+pub fn QubitPurities() -> Element {
+    let labels = purity_labels(CIRCUIT.read().simulator.system());
+
+    rsx! {
+        p { "Purity of each qubit, from 1.00 (pure) down to 0.50 (fully entangled):" }
+        ul {
+            class: "qubitpurities",
+            for label in labels {
+                li { "{label}" }
+            }
+        }
+    }
+}
+
+// One "Qubit N: purity" line per qubit, numbered from 1 like the rest of the editor
+pub fn purity_labels(system: &QubitSystem) -> Vec<String> {
+    system
+        .qubit_purities()
+        .iter()
+        .enumerate()
+        .map(|(idx, purity)| format!("Qubit {}: {purity:.2}", idx + 1))
+        .collect()
+}
+
 #[component]
 pub fn BlochSphere() -> Element {
     let text = r#"
@@ -119,3 +164,24 @@ pub fn Renderer() -> Element {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn bell_purities() {
+        let mut bell = QubitSystem::new(vec![Qubit::zero(), Qubit::zero()]);
+        bell.apply_gate(0, Matrix::hadamard());
+        bell.apply_gate(0, Matrix::cnot());
+
+        let labels = purity_labels(&bell);
+        assert_eq!(labels, vec!["Qubit 1: 0.50", "Qubit 2: 0.50"]);
+        for (label, purity) in labels.iter().zip(bell.qubit_purities()) {
+            assert!(label.ends_with(&format!("{purity:.2}")));
+        }
+
+        let fresh = QubitSystem::new(vec![Qubit::zero()]);
+        assert_eq!(purity_labels(&fresh), vec!["Qubit 1: 1.00"]);
+    }
+}
@@ -0,0 +1,50 @@
+#![feature(test)]
+extern crate test;
+
+use quantum::prelude::*;
+use test::Bencher;
+
+// Ten columns of rotations over four qubits, like a parameter sweep animation replays
+fn rotation_circuit() -> Vec<Vec<Gate>> {
+    (0..10)
+        .map(|column| {
+            let theta = column as f64 * 0.1;
+            vec![Gate::RX(theta), Gate::RY(theta), Gate::RZ(theta), Gate::P(theta)]
+        })
+        .collect()
+}
+
+#[bench]
+fn column_matrices_cached(b: &mut Bencher) {
+    let circuit = rotation_circuit();
+
+    b.iter(|| {
+        for gate in circuit.iter().flatten() {
+            test::black_box(gate.cached_matrix());
+        }
+    });
+}
+
+#[bench]
+fn column_matrices_uncached(b: &mut Bencher) {
+    let circuit = rotation_circuit();
+
+    b.iter(|| {
+        for gate in circuit.iter().flatten() {
+            test::black_box(gate.to_matrix());
+        }
+    });
+}
+
+#[bench]
+fn rotation_circuit_steps(b: &mut Bencher) {
+    let circuit = rotation_circuit();
+
+    b.iter(|| {
+        let mut system = QubitSystem::new(vec![Qubit::zero(); 4]);
+        for column in &circuit {
+            system.apply_gates(column.clone());
+        }
+        test::black_box(system.get_values());
+    });
+}
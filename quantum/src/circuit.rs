@@ -0,0 +1,870 @@
+use std::collections::BTreeMap;
+use std::f64::consts::{PI, TAU};
+use std::time::{Duration, Instant};
+
+use rand::{Rng, seq::SliceRandom};
+use serde::Serialize;
+
+use crate::error::QuantumError;
+use crate::exact::ExactSystem;
+use crate::matrix::Matrix;
+use crate::qubit::{Gate, Qubit, QubitSystem};
+
+// Builds a circuit as a list of columns, each column holding one gate per qubit
+#[macro_export]
+macro_rules! gates {
+    [$([$($elem:expr),* $(,)?]),* $(,)?] => {
+        {
+            use $crate::qubit::Gate::*;
+            vec![
+                $(
+                    vec![$($elem),*]
+                ),*
+            ]
+        }
+    };
+}
+
+// A classical wire that picks a rotation angle instead of switching a gate on or off
+// The target's gate (an RZ, P or other rotation) in the wire's column gets angles[0]
+// if measuring the measured qubit gives 0 and angles[1] if it gives 1
+#[derive(Clone, Debug, PartialEq)]
+pub struct AngleWire {
+    pub column: usize,
+    pub measured: usize,
+    pub target: usize,
+    pub angles: [f64; 2],
+}
+
+// Runs one column of a circuit on the system, the same way the editor steps
+// Wires are (column, measured qubit, conditioned qubit), a conditioned gate only
+// fires if measuring its wire's qubit gives 1. Measurements happen before the column's
+// other gates, each qubit is measured once and returned as (qubit, outcome, probability)
+pub fn step_column(
+    system: &mut QubitSystem,
+    column: &[Gate],
+    wires: &[(usize, usize, usize)],
+) -> Vec<(usize, usize, f64)> {
+    step_column_adaptive(system, column, wires, &[])
+}
+
+// step_column with angle wires as well, see AngleWire
+pub fn step_column_adaptive(
+    system: &mut QubitSystem,
+    column: &[Gate],
+    wires: &[(usize, usize, usize)],
+    angle_wires: &[AngleWire],
+) -> Vec<(usize, usize, f64)> {
+    let (gates, measurements) = resolve_column(system, column, wires, angle_wires);
+    system.apply_gates(gates);
+    measurements
+}
+
+// Time one column spent building its full operator and then multiplying the state by it
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColumnTiming {
+    pub build: Duration,
+    pub apply: Duration,
+}
+
+// step_column_adaptive, timing the two halves of applying the column
+// Uses std::time::Instant, which isn't available on wasm32-unknown-unknown
+pub fn step_column_profiled(
+    system: &mut QubitSystem,
+    column: &[Gate],
+    wires: &[(usize, usize, usize)],
+    angle_wires: &[AngleWire],
+) -> (Vec<(usize, usize, f64)>, ColumnTiming) {
+    let (gates, measurements) = resolve_column(system, column, wires, angle_wires);
+
+    let start = Instant::now();
+    let operator = QubitSystem::column_operator(&gates);
+    let build = start.elapsed();
+
+    let start = Instant::now();
+    system.apply_column_operator(&operator, &gates);
+    let apply = start.elapsed();
+
+    (measurements, ColumnTiming { build, apply })
+}
+
+// Does a column's measurements and classical wires, leaving only unitary gates to apply
+// A wire from a MeasureReset qubit uses its outcome from before the reset
+fn resolve_column(
+    system: &mut QubitSystem,
+    column: &[Gate],
+    wires: &[(usize, usize, usize)],
+    angle_wires: &[AngleWire],
+) -> (Vec<Gate>, Vec<(usize, usize, f64)>) {
+    let mut gates = column.to_vec();
+    let mut measurements: Vec<(usize, usize, f64)> = Vec::new();
+
+    for (qubit, gate) in gates.iter_mut().enumerate() {
+        if *gate == Gate::M || *gate == Gate::MeasureReset {
+            let (outcome, probability) = system.measure_with_probability(qubit);
+            measurements.push((qubit, outcome, probability));
+            if *gate == Gate::MeasureReset && outcome == 1 {
+                system.apply(&Gate::X, &[qubit]);
+            }
+            *gate = Gate::I;
+        }
+    }
+
+    // Reuses the outcome if the qubit has already been measured in this column
+    let mut measure = |system: &mut QubitSystem, qubit: usize| match measurements
+        .iter()
+        .find(|m| m.0 == qubit)
+    {
+        Some(measurement) => measurement.1,
+        None => {
+            let (outcome, probability) = system.measure_with_probability(qubit);
+            measurements.push((qubit, outcome, probability));
+            outcome
+        }
+    };
+
+    for wire in wires {
+        if measure(system, wire.1) == 0 {
+            gates[wire.2] = Gate::I;
+        }
+    }
+
+    for wire in angle_wires {
+        let outcome = measure(system, wire.measured);
+        gates[wire.target] = gates[wire.target].with_angle(wire.angles[outcome]);
+    }
+
+    (gates, measurements)
+}
+
+// Settles the wires whose measured qubit already holds a classical outcome from an earlier
+// column, switching off or re-angling their gates, and returns the wires left over
+// The leftovers measure their qubit as part of the column, as does any wire whose qubit
+// is measured again in this column, since the new outcome replaces the stored one
+// classical holds the latest outcome of each qubit, None if it hasn't been measured yet
+pub fn apply_stored_wires(
+    gates: &mut [Gate],
+    wires: &[(usize, usize, usize)],
+    angle_wires: &[AngleWire],
+    classical: &[Option<usize>],
+) -> (Vec<(usize, usize, usize)>, Vec<AngleWire>) {
+    let measured: Vec<bool> =
+        gates.iter().map(|gate| matches!(gate, Gate::M | Gate::MeasureReset)).collect();
+    let remeasured = |qubit: usize| measured[qubit];
+    let stored = |qubit: usize| classical.get(qubit).copied().flatten();
+
+    let mut remaining_wires = Vec::new();
+    for wire in wires {
+        match stored(wire.1) {
+            Some(outcome) if !remeasured(wire.1) => {
+                if outcome == 0 {
+                    gates[wire.2] = Gate::I;
+                }
+            }
+            _ => remaining_wires.push(*wire),
+        }
+    }
+
+    let mut remaining_angle_wires = Vec::new();
+    for wire in angle_wires {
+        match stored(wire.measured) {
+            Some(outcome) if !remeasured(wire.measured) => {
+                gates[wire.target] = gates[wire.target].with_angle(wire.angles[outcome]);
+            }
+            _ => remaining_angle_wires.push(wire.clone()),
+        }
+    }
+
+    (remaining_wires, remaining_angle_wires)
+}
+
+// Runs a whole circuit from |0...0⟩ without any UI
+// Clifford+T circuits without wires are run exactly, so their amplitudes carry no float error
+pub fn run_circuit(gates: &[Vec<Gate>], wires: &[(usize, usize, usize)]) -> QubitSystem {
+    if wires.is_empty()
+        && let Some(exact) = ExactSystem::run(gates)
+    {
+        return exact.to_system();
+    }
+
+    let mut system = QubitSystem::new(vec![Qubit::zero(); gates[0].len()]);
+    run_circuit_on(&mut system, gates, wires);
+    system
+}
+
+// Runs a whole circuit on an already prepared system
+// A wire's column is its conditioned gate's, the measurement can be in that column or before
+pub fn run_circuit_on(
+    system: &mut QubitSystem,
+    gates: &[Vec<Gate>],
+    wires: &[(usize, usize, usize)],
+) {
+    let mut classical = vec![None; system.num_qubits()];
+
+    for (idx, column) in gates.iter().enumerate() {
+        let mut column = column.clone();
+        let column_wires: Vec<_> = wires.iter().filter(|wire| wire.0 == idx).copied().collect();
+        let (column_wires, _) = apply_stored_wires(&mut column, &column_wires, &[], &classical);
+
+        for (qubit, outcome, _) in step_column(system, &column, &column_wires) {
+            classical[qubit] = Some(outcome);
+        }
+    }
+}
+
+// The whole circuit as one matrix, later columns multiplied on the left
+// Columns of nothing but identities and continuation cells are skipped outright
+// Measurements count as the identity, so this only means much for unitary circuits
+pub fn full_unitary(gates: &[Vec<Gate>]) -> Matrix {
+    let size = 1 << gates.first().map_or(0, Vec::len);
+
+    gates
+        .iter()
+        .filter(|column| !column.iter().all(Gate::is_identity))
+        .fold(Matrix::identity(size), |unitary, column| {
+            QubitSystem::column_operator(column).matmul(&unitary)
+        })
+}
+
+// Builds up a circuit gate by gate instead of writing out every column by hand
+// Each gate goes into the earliest column where all of its qubits are free,
+// and the circuit grows to however many qubits are used
+#[derive(Default)]
+pub struct Circuit {
+    columns: Vec<Vec<Gate>>,
+    // First free column of each qubit
+    frontier: Vec<usize>,
+}
+
+impl Circuit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn num_qubits(&self) -> usize {
+        self.frontier.len()
+    }
+
+    // Places any gate, multi-qubit gates cover qubit and the qubits directly after it
+    pub fn gate(mut self, qubit: usize, gate: Gate) -> Self {
+        let size = gate.num_qubits().max(1);
+        while self.frontier.len() < qubit + size {
+            self.frontier.push(0);
+            for column in &mut self.columns {
+                column.push(Gate::I);
+            }
+        }
+
+        let column = *self.frontier[qubit..qubit + size].iter().max().unwrap();
+        while self.columns.len() <= column {
+            self.columns.push(vec![Gate::I; self.frontier.len()]);
+        }
+
+        self.columns[column][qubit] = gate;
+        for i in 1..size {
+            self.columns[column][qubit + i] = Gate::Other(String::from("none"));
+        }
+        for i in 0..size {
+            self.frontier[qubit + i] = column + 1;
+        }
+
+        self
+    }
+
+    pub fn h(self, qubit: usize) -> Self {
+        self.gate(qubit, Gate::H)
+    }
+
+    pub fn x(self, qubit: usize) -> Self {
+        self.gate(qubit, Gate::X)
+    }
+
+    pub fn rx(self, qubit: usize, theta: f64) -> Self {
+        self.gate(qubit, Gate::RX(theta))
+    }
+
+    pub fn measure(self, qubit: usize) -> Self {
+        self.gate(qubit, Gate::M)
+    }
+
+    // CNOT between any two qubits
+    // The CNOT gate only acts on a control directly above its target, so far apart
+    // qubits are swapped next to each other first, and an upside down CNOT is made by
+    // surrounding it with Hadamards
+    pub fn cnot(self, control: usize, target: usize) -> Self {
+        assert_ne!(control, target, "control and target must be different qubits");
+
+        // Each entry swaps that qubit with the one after it
+        let swaps: Vec<usize> = if target > control {
+            (control + 1..target).rev().collect()
+        } else {
+            (target..control - 1).collect()
+        };
+
+        let mut circuit = swaps.iter().fold(self, |c, s| c.gate(*s, Gate::SWAP));
+        if target > control {
+            circuit = circuit.gate(control, Gate::CNOT);
+        } else {
+            let moved = control - 1;
+            circuit = circuit.h(moved).h(control).gate(moved, Gate::CNOT).h(moved).h(control);
+        }
+
+        swaps.iter().rev().fold(circuit, |c, s| c.gate(*s, Gate::SWAP))
+    }
+
+    // Swaps any two qubits by walking the first down to the second with adjacent SWAPs,
+    // then walking the second (now one above) back up to where the first was
+    pub fn swap(self, a: usize, b: usize) -> Self {
+        let (low, high) = (a.min(b), a.max(b));
+        let down = (low..high).fold(self, |c, s| c.gate(s, Gate::SWAP));
+        (low..high.saturating_sub(1)).rev().fold(down, |c, s| c.gate(s, Gate::SWAP))
+    }
+
+    // Controlled phase between any two qubits
+    // CP is symmetric, so unlike cnot the qubits only need to be brought next to each other
+    pub fn cphase(self, control: usize, target: usize, theta: f64) -> Self {
+        assert_ne!(control, target, "control and target must be different qubits");
+
+        let (low, high) = (control.min(target), control.max(target));
+        let swaps: Vec<usize> = (low + 1..high).rev().collect();
+        let circuit = swaps.iter().fold(self, |c, s| c.gate(*s, Gate::SWAP));
+        let circuit = circuit.gate(low, Gate::CP(theta));
+        swaps.iter().rev().fold(circuit, |c, s| c.gate(*s, Gate::SWAP))
+    }
+
+    // Inverse quantum Fourier transform on the listed qubits, first listed most significant
+    // Takes Σ e^(2πi·xk/2^n)|k⟩ back to |x⟩, as at the end of phase estimation
+    pub fn qft_inverse(self, qubits: &[usize]) -> Self {
+        let n = qubits.len();
+        let mut circuit = (0..n / 2).fold(self, |c, i| c.swap(qubits[i], qubits[n - 1 - i]));
+
+        for j in (0..n).rev() {
+            for k in (j + 1..n).rev() {
+                let theta = -PI / (1 << (k - j)) as f64;
+                circuit = circuit.cphase(qubits[k], qubits[j], theta);
+            }
+            circuit = circuit.h(qubits[j]);
+        }
+
+        circuit
+    }
+
+    // Lines every qubit up so nothing after the barrier shares a column with anything before it
+    pub fn barrier(mut self) -> Self {
+        let column = self.frontier.iter().copied().max().unwrap_or(0);
+        self.frontier.iter_mut().for_each(|f| *f = column);
+        self
+    }
+
+    pub fn build(self) -> Vec<Vec<Gate>> {
+        self.columns
+    }
+}
+
+// The controlled version of a whole circuit, on a new control qubit put above the others
+// Every gate becomes a Controlled gate, with the control swapped down next to it first
+// Measurements, asserts and custom gates have no controlled version, so they're an error
+pub fn controlled_circuit(gates: &[Vec<Gate>]) -> Result<Vec<Vec<Gate>>, QuantumError> {
+    let registers = gates.first().map_or(0, Vec::len);
+    let mut circuit = Circuit::new();
+
+    for column in gates {
+        for (register, gate) in column.iter().enumerate() {
+            match gate {
+                Gate::I => continue,
+                Gate::Other(name) if name == "none" => continue,
+                Gate::M | Gate::MeasureReset | Gate::Assert(_) | Gate::Other(_) => {
+                    return Err(QuantumError::NotControllable(format!("{gate:?}")));
+                }
+                _ => {}
+            }
+
+            // Walks the control from the top to just above the gate and back again
+            circuit = (0..register).fold(circuit, |c, s| c.gate(s, Gate::SWAP));
+            circuit = circuit.gate(register, Gate::Controlled(Box::new(gate.clone())));
+            circuit = (0..register).rev().fold(circuit, |c, s| c.gate(s, Gate::SWAP));
+        }
+    }
+
+    let mut controlled = circuit.build();
+    for column in &mut controlled {
+        column.resize(registers + 1, Gate::I);
+    }
+    Ok(controlled)
+}
+
+// XORs every input qubit onto the target with a CNOT each, so the target flips once
+// per input that's |1⟩ and ends up holding their parity (if it started at |0⟩)
+pub fn parity_circuit(inputs: &[usize], target: usize) -> Vec<Vec<Gate>> {
+    inputs.iter().fold(Circuit::new(), |circuit, input| circuit.cnot(*input, target)).build()
+}
+
+// Summary of a circuit's size and gate usage, serializable for reports
+#[derive(Debug, PartialEq, Serialize)]
+pub struct CircuitStats {
+    pub qubits: usize,
+    // Columns that hold at least one gate
+    pub depth: usize,
+    // How many of each gate there are, by name, leaving out identities
+    pub gate_counts: BTreeMap<String, usize>,
+    // T and T† gates, the expensive ones on error corrected hardware
+    pub t_count: usize,
+    pub measurements: usize,
+}
+
+pub fn circuit_stats(gates: &[Vec<Gate>]) -> CircuitStats {
+    let is_gate = |gate: &&Gate| **gate != Gate::I && **gate != Gate::Other(String::from("none"));
+
+    let mut gate_counts = BTreeMap::new();
+    for gate in gates.iter().flatten().filter(is_gate) {
+        *gate_counts.entry(format!("{gate:?}")).or_insert(0) += 1;
+    }
+    let count = |name: &str| gate_counts.get(name).copied().unwrap_or(0);
+
+    CircuitStats {
+        qubits: gates.first().map_or(0, |column| column.len()),
+        depth: gates.iter().filter(|column| column.iter().any(|gate| is_gate(&gate))).count(),
+        t_count: count("T") + count("Tdg"),
+        measurements: count("M") + count("MR"),
+        gate_counts,
+    }
+}
+
+// Gates that undo themselves when applied twice in a row
+const SELF_INVERSE: [Gate; 3] = [Gate::X, Gate::H, Gate::Z];
+
+// Scans each qubit's gates in column order and returns readable warnings about
+// adjacent self-inverse pairs (XX, HH, ZZ) and gates placed after a measurement
+// Qubits and columns are numbered from 1 to match the editor, and a continuation cell
+// is reported as the multi-qubit gate it belongs to
+pub fn lint_circuit(gates: &[Vec<Gate>]) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let registers = gates.first().map_or(0, |column| column.len());
+    let continuation = |gate: &&Gate| matches!(gate, Gate::Other(name) if name == "none");
+
+    for register in 0..registers {
+        let mut previous: Option<(usize, &Gate)> = None;
+        let mut measured: Option<usize> = None;
+
+        for (column, cells) in gates.iter().enumerate() {
+            let Some(gate) = cells[..=register].iter().rev().find(|gate| !continuation(gate))
+            else {
+                continue;
+            };
+            if *gate == Gate::I {
+                continue;
+            }
+
+            if let Some(measured_column) = measured {
+                warnings.push(format!(
+                    "Qubit {}: {gate:?} in column {} comes after the measurement in column {}",
+                    register + 1,
+                    column + 1,
+                    measured_column + 1
+                ));
+            }
+
+            if let Some((previous_column, previous_gate)) = previous
+                && previous_gate == gate
+                && SELF_INVERSE.contains(gate)
+            {
+                warnings.push(format!(
+                    "Qubit {}: {gate:?} gates in columns {} and {} cancel each other out",
+                    register + 1,
+                    previous_column + 1,
+                    column + 1
+                ));
+            }
+
+            if *gate == Gate::M && measured.is_none() {
+                measured = Some(column);
+            }
+
+            previous = Some((column, gate));
+        }
+    }
+
+    warnings
+}
+
+// Every unitary gate, random_circuit picks from these and fills in random angles
+const RANDOM_GATES: [Gate; 24] = [
+    Gate::I,
+    Gate::X,
+    Gate::Y,
+    Gate::Z,
+    Gate::H,
+    Gate::S,
+    Gate::Sdg,
+    Gate::T,
+    Gate::Tdg,
+    Gate::P(0.0),
+    Gate::RX(0.0),
+    Gate::RY(0.0),
+    Gate::RZ(0.0),
+    Gate::U3(0.0, 0.0, 0.0),
+    Gate::Rot([0.0, 0.0, 1.0], 0.0),
+    Gate::CNOT,
+    Gate::ACNOT,
+    Gate::CZ,
+    Gate::CP(0.0),
+    Gate::SWAP,
+    Gate::CCX,
+    Gate::CSWAP,
+    Gate::CCZ,
+    Gate::CCCX,
+];
+
+// A circuit of random unitary gates for stress testing, depth columns on num_qubits qubits
+// Each column is filled from the top with whichever gates still fit below
+pub fn random_circuit(num_qubits: usize, depth: usize, rng: &mut impl Rng) -> Vec<Vec<Gate>> {
+    let mut gates = Vec::with_capacity(depth);
+
+    for _ in 0..depth {
+        let mut column = Vec::with_capacity(num_qubits);
+        while column.len() < num_qubits {
+            let free = num_qubits - column.len();
+            let fitting: Vec<&Gate> =
+                RANDOM_GATES.iter().filter(|gate| gate.num_qubits() <= free).collect();
+            let mut gate = (*fitting.choose(rng).unwrap()).clone();
+            for param in 0..gate.params().len() {
+                gate = gate.with_param(param, rng.r#gen::<f64>() * TAU);
+            }
+
+            let size = gate.num_qubits();
+            column.push(gate);
+            column.extend(vec![Gate::Other(String::from("none")); size - 1]);
+        }
+        gates.push(column);
+    }
+
+    gates
+}
+
+// Sizes in pixels, matching the editor's layout: 26px gates with 7px between qubits
+const SVG_ROW: f64 = 33.0;
+const SVG_COLUMN: f64 = 44.0;
+const SVG_GATE: f64 = 26.0;
+const SVG_MARGIN: f64 = 10.0;
+
+// How many of a multi-qubit gate's qubits are controls, the rest go in the gate's box
+fn svg_controls(gate: &Gate) -> usize {
+    match gate {
+        Gate::CNOT | Gate::ACNOT | Gate::CZ | Gate::CP(_) | Gate::CSWAP => 1,
+        Gate::CCX | Gate::CCZ => 2,
+        Gate::CCCX => 3,
+        Gate::Controlled(inner) => 1 + svg_controls(inner),
+        _ => 0,
+    }
+}
+
+fn svg_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// Draws the circuit as a standalone SVG: a line per qubit, a labelled box per gate,
+// dots for controls (open for ACNOT's |0⟩ control) and a line for each classical wire
+pub fn to_svg(gates: &[Vec<Gate>], wires: &[(usize, usize, usize)], num_qubits: usize) -> String {
+    let width = SVG_MARGIN * 2.0 + gates.len() as f64 * SVG_COLUMN;
+    let height = SVG_MARGIN * 2.0 + num_qubits as f64 * SVG_ROW - (SVG_ROW - SVG_GATE);
+    let x = |column: usize| SVG_MARGIN + column as f64 * SVG_COLUMN + SVG_COLUMN / 2.0;
+    let y = |qubit: usize| SVG_MARGIN + qubit as f64 * SVG_ROW + SVG_GATE / 2.0;
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" \
+         viewBox=\"0 0 {width} {height}\">\n"
+    );
+
+    for qubit in 0..num_qubits {
+        svg += &format!(
+            "<line x1=\"{0}\" y1=\"{1}\" x2=\"{2}\" y2=\"{1}\" stroke=\"black\"/>\n",
+            SVG_MARGIN,
+            y(qubit),
+            width - SVG_MARGIN
+        );
+    }
+
+    for &(column, measured, conditioned) in wires {
+        svg += &format!(
+            "<line x1=\"{0}\" y1=\"{1}\" x2=\"{0}\" y2=\"{2}\" stroke=\"black\" \
+             stroke-dasharray=\"4 2\"/>\n",
+            x(column),
+            y(measured),
+            y(conditioned)
+        );
+    }
+
+    for (column, gates) in gates.iter().enumerate() {
+        for (qubit, gate) in gates.iter().enumerate() {
+            if *gate == Gate::I || *gate == Gate::Other(String::from("none")) {
+                continue;
+            }
+
+            let size = gate.num_qubits().max(1);
+            let controls = svg_controls(gate);
+            if size > 1 {
+                svg += &format!(
+                    "<line x1=\"{0}\" y1=\"{1}\" x2=\"{0}\" y2=\"{2}\" stroke=\"black\"/>\n",
+                    x(column),
+                    y(qubit),
+                    y(qubit + size - 1)
+                );
+            }
+
+            for control in qubit..qubit + controls {
+                let fill = if *gate == Gate::ACNOT { "white" } else { "black" };
+                svg += &format!(
+                    "<circle cx=\"{}\" cy=\"{}\" r=\"4\" fill=\"{fill}\" stroke=\"black\"/>\n",
+                    x(column),
+                    y(control)
+                );
+            }
+
+            let top = y(qubit + controls) - SVG_GATE / 2.0;
+            let bottom = y(qubit + size - 1) + SVG_GATE / 2.0;
+            svg += &format!(
+                "<rect x=\"{}\" y=\"{top}\" width=\"{SVG_GATE}\" height=\"{}\" \
+                 fill=\"#ffffeb\" stroke=\"black\"/>\n",
+                x(column) - SVG_GATE / 2.0,
+                bottom - top
+            );
+            svg += &format!(
+                "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\" \
+                 dominant-baseline=\"central\" font-size=\"10\">{}</text>\n",
+                x(column),
+                (top + bottom) / 2.0,
+                svg_escape(&format!("{gate:?}"))
+            );
+        }
+    }
+
+    svg + "</svg>\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use super::*;
+    use crate::{c, complex::ComplexNumber};
+    use Gate::*;
+
+    #[test]
+    pub fn bell_builder() {
+        let built = Circuit::new().h(0).cnot(0, 1).build();
+
+        assert_eq!(built, gates![[H, I], [CNOT, Other(String::from("none"))]]);
+    }
+
+    #[test]
+    pub fn identity_padding() {
+        let gates = gates![
+            [H, I, I],
+            [CNOT, Other(String::from("none")), T],
+            [I, SWAP, Other(String::from("none"))]
+        ];
+        let mut padded = vec![vec![I; 3]; 4];
+        for column in &gates {
+            padded.push(column.clone());
+            padded.extend(vec![vec![I, I, Assert(String::from("0"))]; 3]);
+        }
+
+        let unitary = full_unitary(&gates);
+        assert!(unitary.is_unitary(1e-12));
+        assert!(full_unitary(&padded).approx_eq(&unitary, 1e-12));
+        assert!(full_unitary(&[vec![I; 3]]).approx_eq(&Matrix::identity(8), 1e-12));
+
+        // The first column of the unitary is the state the circuit leaves |000⟩ in
+        let values = run_circuit(&gates, &[]).get_values();
+        assert!((0..8).all(|i| unitary[i][0].approx_eq(&values[i], 1e-12)));
+    }
+
+    #[test]
+    pub fn parity() {
+        for (inputs, expected) in [([1, 1, 1], 1), ([1, 0, 1], 0), ([0, 0, 1], 1)] {
+            let mut first: Vec<Gate> = inputs.iter().map(|i| if *i == 1 { X } else { I }).collect();
+            first.push(I);
+            let mut gates = vec![first];
+            gates.extend(parity_circuit(&[0, 1, 2], 3));
+
+            let mut system = run_circuit(&gates, &[]);
+            assert_eq!(system.measure(), [inputs.to_vec(), vec![expected]].concat());
+        }
+
+        // The target doesn't have to come after the inputs
+        let mut gates = gates![[I, X, X]];
+        gates.extend(parity_circuit(&[2, 1], 0));
+        assert_eq!(run_circuit(&gates, &[]).measure(), vec![0, 1, 1]);
+    }
+
+    #[test]
+    pub fn stats() {
+        let none = || Other(String::from("none"));
+        let stats = circuit_stats(&gates![[T, H], [Tdg, I], [I, I], [CNOT, none()], [M, T]]);
+
+        assert_eq!(stats.qubits, 2);
+        assert_eq!(stats.depth, 4);
+        assert_eq!(stats.t_count, 3);
+        assert_eq!(stats.measurements, 1);
+        assert_eq!(stats.gate_counts.get("CNOT"), Some(&1));
+        assert_eq!(stats.gate_counts.get("T"), Some(&2));
+        assert_eq!(stats.gate_counts.get("none"), None);
+    }
+
+    #[test]
+    pub fn builder_layers() {
+        let built = Circuit::new().h(0).h(1).rx(2, 0.5).measure(0).barrier().x(2).build();
+        let expected = gates![[H, H, RX(0.5)], [M, I, I], [I, I, X]];
+
+        assert_eq!(built, expected);
+    }
+
+    #[test]
+    pub fn routed_cnots() {
+        // Far apart and upside down CNOTs should act like the plain one would
+        let cases = [(0, 2, [1, 0, 1]), (2, 0, [1, 0, 1]), (1, 0, [1, 1, 0]), (3, 1, [0, 1, 0])];
+
+        for (control, target, expected) in cases {
+            let gates = Circuit::new().x(control).gate(3, Gate::I).cnot(control, target).build();
+            let mut system = run_circuit(&gates, &[]);
+            let mut measured = system.measure();
+            measured.truncate(3);
+
+            assert_eq!(measured, expected, "cnot({control}, {target})");
+        }
+    }
+
+    #[test]
+    pub fn routed_swaps_and_phases() {
+        let gates = Circuit::new().x(0).gate(3, Gate::I).swap(0, 3).build();
+        assert_eq!(run_circuit(&gates, &[]).measure(), vec![0, 0, 0, 1]);
+
+        // Only |1⟩|1⟩ picks up the phase, however far apart and whichever way round
+        let plus = ComplexNumber::SQRT_HALF;
+        for (control, target) in [(0, 2), (2, 0)] {
+            let gates = Circuit::new().h(0).x(2).cphase(control, target, PI).build();
+            let values = run_circuit(&gates, &[]).get_values();
+
+            assert!(values[1].approx_eq(&plus, 1e-9));
+            assert!(values[5].approx_eq(&(plus * c!(-1.0)), 1e-9));
+        }
+    }
+
+    #[test]
+    pub fn controlled_hadamard() {
+        let controlled = controlled_circuit(&gates![[H]]).unwrap();
+        assert_eq!(controlled, gates![[Controlled(Box::new(H)), Other(String::from("none"))]]);
+
+        // Nothing happens with the control off
+        assert_eq!(run_circuit(&controlled, &[]).get_values()[0], c!(1.0));
+
+        let mut on = gates![[X, I]];
+        on.extend(controlled);
+        let values = run_circuit(&on, &[]).get_values();
+        assert!(values[2].approx_eq(&ComplexNumber::SQRT_HALF, 1e-9));
+        assert!(values[3].approx_eq(&ComplexNumber::SQRT_HALF, 1e-9));
+    }
+
+    #[test]
+    pub fn controlled_circuits() {
+        // The X on the last qubit only fires with the new control on top set
+        let controlled = controlled_circuit(&gates![[I, I, X]]).unwrap();
+        assert!(controlled.iter().all(|column| column.len() == 4));
+        for (control, expected) in [(I, vec![0, 0, 0, 0]), (X, vec![1, 0, 0, 1])] {
+            let mut gates = vec![vec![control, I, I, I]];
+            gates.extend(controlled.clone());
+            assert_eq!(run_circuit(&gates, &[]).measure(), expected);
+        }
+
+        let error = controlled_circuit(&gates![[H], [M]]).unwrap_err();
+        assert_eq!(error, QuantumError::NotControllable(String::from("M")));
+    }
+
+    #[test]
+    pub fn cancelling_pairs() {
+        let gates = vec![vec![X, H], vec![X, I], vec![I, H], vec![Z, Z], vec![Z, I]];
+        let warnings = lint_circuit(&gates);
+
+        assert_eq!(
+            warnings,
+            vec![
+                "Qubit 1: X gates in columns 1 and 2 cancel each other out",
+                "Qubit 1: Z gates in columns 4 and 5 cancel each other out",
+                "Qubit 2: H gates in columns 1 and 3 cancel each other out",
+            ]
+        );
+    }
+
+    #[test]
+    pub fn separated_pairs() {
+        // The CNOT's continuation cell sits between the two X gates on qubit 2
+        let none = Other(String::from("none"));
+        let gates = vec![vec![I, X], vec![CNOT, none], vec![I, X], vec![Y, I], vec![Y, I]];
+
+        assert!(lint_circuit(&gates).is_empty());
+    }
+
+    #[test]
+    pub fn gates_after_measurement() {
+        let gates = vec![vec![H, I], vec![M, X], vec![H, I]];
+        let warnings = lint_circuit(&gates);
+
+        assert_eq!(
+            warnings,
+            vec!["Qubit 1: H in column 3 comes after the measurement in column 2"]
+        );
+
+        // The CNOT crosses the measured second qubit, and is named rather than its "none" cell
+        let none = Other(String::from("none"));
+        assert_eq!(
+            lint_circuit(&[vec![I, M], vec![CNOT, none]]),
+            vec!["Qubit 2: CNOT in column 2 comes after the measurement in column 1"]
+        );
+    }
+
+    #[test]
+    pub fn single_gate_svg() {
+        let svg = to_svg(&[vec![H]], &[], 1);
+
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"64\""));
+        assert!(svg.contains(
+            "<rect x=\"19\" y=\"10\" width=\"26\" height=\"26\" fill=\"#ffffeb\" stroke=\"black\"/>"
+        ));
+        assert!(svg.contains(">H</text>"));
+        assert_eq!(svg.matches("<rect").count(), 1);
+        assert!(!svg.contains("<circle"));
+    }
+
+    #[test]
+    pub fn controlled_svg() {
+        let none = Other(String::from("none"));
+        let svg = to_svg(&[vec![CNOT, none.clone()], vec![M, X]], &[(1, 0, 1)], 2);
+
+        // One control dot, and the CNOT's box only covers its target
+        assert_eq!(svg.matches("<circle").count(), 1);
+        assert!(svg.contains("height=\"26\" fill"));
+        assert!(svg.contains(">CNOT</text>") && svg.contains(">M</text>"));
+        assert!(svg.contains("stroke-dasharray"));
+        assert!(!svg.contains(">none<"));
+    }
+
+    #[test]
+    pub fn random_backends_agree() {
+        let mut rng = StdRng::seed_from_u64(1431);
+        let gates = random_circuit(4, 10, &mut rng);
+        assert_eq!(gates.len(), 10);
+        assert!(gates.iter().all(|column| column.len() == 4));
+
+        let mut dense = QubitSystem::new(vec![Qubit::zero(); 4]);
+        let mut fast = dense.clone();
+        for column in &gates {
+            dense.apply_gates(column.clone());
+            fast.apply_column_fast(column);
+        }
+
+        for (a, b) in dense.get_values().iter().zip(fast.get_values()) {
+            assert!(a.approx_eq(&b, 1e-9), "{a:?} != {b:?}");
+        }
+        assert!(dense.get_global_phase().approx_eq(&fast.get_global_phase(), 1e-9));
+    }
+}
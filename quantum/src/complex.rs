@@ -1,15 +1,19 @@
 //trait Number = Sized + Copy + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> + Div<Output = Self>;
 use crate::c;
 
+use serde::{Deserialize, Serialize};
 use std::{
     f64::consts::SQRT_2,
     fmt::{Debug, Display},
-    ops::{Add, Index, Mul, AddAssign, Div}
+    ops::{Add, Index, Mul, AddAssign, Div, Sub}
 };
 
-#[derive(Copy, Clone)]
+// Serialized as {"re": .., "im": ..}
+#[derive(Copy, Clone, Serialize, Deserialize)]
 pub struct ComplexNumber {
+    #[serde(rename = "re")]
     pub real: f64,
+    #[serde(rename = "im")]
     pub imaginary: f64,
 }
 
@@ -41,6 +45,25 @@ impl ComplexNumber {
         }
         pretty_num(self.real)
     }
+
+    // pretty with each part to precision significant digits, and no 1/√2 shorthand
+    pub fn pretty_precision(&self, precision: usize) -> String {
+        // Parts too small to show at this precision are left out
+        let tiny = 0.5 * 10f64.powi(-(precision as i32));
+        if self.real.abs() < tiny {
+            return format!("{}i", significant(self.imaginary, precision));
+        }
+
+        let real = significant(self.real, precision);
+        let imaginary = significant(self.imaginary.abs(), precision);
+        if self.imaginary > tiny {
+            return format!("{real} + {imaginary}i");
+        }
+        if self.imaginary < -tiny {
+            return format!("{real} - {imaginary}i");
+        }
+        real
+    }
 }
 
 pub fn pretty_num(n: f64) -> String {
@@ -51,16 +74,61 @@ pub fn pretty_num(n: f64) -> String {
     format!("{n:.2}")
 }
 
+// n to precision significant digits without trailing zeros, e.g. 0.70710 to 3 is 0.707
+pub fn significant(n: f64, precision: usize) -> String {
+    if n == 0.0 || !n.is_finite() {
+        return format!("{}", n.abs());
+    }
+
+    let magnitude = n.abs().log10().floor() as i32;
+    let decimals = (precision as i32 - 1 - magnitude).max(0) as usize;
+    let text = format!("{n:.decimals$}");
+    if text.contains('.') {
+        text.trim_end_matches('0').trim_end_matches('.').to_string()
+    } else {
+        text
+    }
+}
+
 // Complex Specific Operations
 impl ComplexNumber {
     pub fn conjugate(&self) -> Self {
         ComplexNumber { real: self.real, imaginary: self.imaginary * -1.0 }
     }
 
+    // Whether both parts are within epsilon of the other number's
+    pub fn approx_eq(&self, other: &Self, epsilon: f64) -> bool {
+        (self.real - other.real).abs() < epsilon
+            && (self.imaginary - other.imaginary).abs() < epsilon
+    }
+
     pub fn abs_squared(&self) -> f64 {
         self.real * self.real + self.imaginary * self.imaginary
     }
 
+    // Distance from the origin, |z|
+    pub fn magnitude(&self) -> f64 {
+        self.abs_squared().sqrt()
+    }
+
+    // Angle from the positive real axis in radians, in (-π, π]
+    pub fn arg(&self) -> f64 {
+        self.imaginary.atan2(self.real)
+    }
+
+    // Polar form r∠θ with the angle in degrees, e.g. i is 1∠90°
+    // Easier to read than a + bi when the phase is what matters
+    pub fn polar_string(&self) -> String {
+        let round = |n: f64| (n * 100.0).round() / 100.0 + 0.0;
+        format!("{}∠{}°", round(self.magnitude()), round(self.arg().to_degrees()))
+    }
+
+    // polar_string to precision significant digits
+    pub fn polar_precision(&self, precision: usize) -> String {
+        let magnitude = significant(self.magnitude(), precision);
+        format!("{magnitude}∠{}°", significant(self.arg().to_degrees(), precision))
+    }
+
     // pub fn pow(&self, power: Self) -> Self {
     //
     // }
@@ -76,8 +144,12 @@ impl ComplexNumber {
     }
 }
 
+// {:#} prints the polar form instead
 impl Display for ComplexNumber {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() {
+            return f.write_str(&self.polar_string());
+        }
         f.write_fmt(format_args!("{} + {}i", self.real, self.imaginary))
     }
 }
@@ -91,9 +163,11 @@ impl Debug for ComplexNumber {
     }
 }
 
+// Loose on purpose, so hand written values like c!(0.71) match 1/√2
+// Use approx_eq when a different tolerance is needed
 impl PartialEq for ComplexNumber {
     fn eq(&self, other: &Self) -> bool {
-        (self.real - other.real).abs() < 0.05 && (self.imaginary - other.imaginary).abs() < 0.05
+        self.approx_eq(other, 0.05)
     }
 }
 
@@ -109,6 +183,17 @@ impl Add for ComplexNumber {
     }
 }
 
+impl Sub for ComplexNumber {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        let real = self.real - rhs.real;
+        let imaginary = self.imaginary - rhs.imaginary;
+
+        Self { real, imaginary }
+    }
+}
+
 impl AddAssign for ComplexNumber {
     fn add_assign(&mut self, rhs: Self) {
         *self = *self + rhs
@@ -147,3 +232,32 @@ macro_rules! c {
         ComplexNumber::new($real, $imag)
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn polar() {
+        assert_eq!(c!(0.0, 1.0).polar_string(), "1∠90°");
+        assert_eq!(c!(-1.0).polar_string(), "1∠180°");
+        assert_eq!(c!(1.0).polar_string(), "1∠0°");
+        assert_eq!(c!(0.0, -2.0).polar_string(), "2∠-90°");
+        assert_eq!(format!("{:#}", c!(0.0, 1.0)), "1∠90°");
+        assert_eq!(c!(0.0, 1.0).polar_precision(3), "1∠90°");
+        assert_eq!(ComplexNumber::SQRT_HALF.polar_precision(4), "0.7071∠0°");
+    }
+
+    #[test]
+    pub fn precision() {
+        let half = ComplexNumber::SQRT_HALF;
+        assert_eq!(half.pretty_precision(3), "0.707");
+        assert_eq!(half.pretty_precision(5), "0.70711");
+        assert_eq!(c!(0.5, -0.25).pretty_precision(3), "0.5 - 0.25i");
+        assert_eq!(c!(0.0, 1.0).pretty_precision(3), "1i");
+
+        assert_eq!(significant(123.456, 3), "123");
+        assert_eq!(significant(-0.0012345, 2), "-0.0012");
+        assert_eq!(significant(0.0, 3), "0");
+    }
+}
@@ -0,0 +1,46 @@
+use std::error::Error;
+use std::fmt::{self, Display};
+
+// Why a checked (try_) operation refused its input, the unchecked versions panic instead
+#[derive(Clone, Debug, PartialEq)]
+pub enum QuantumError {
+    // A matrix or state vector had `found` entries per side where `expected` were needed
+    DimensionMismatch { expected: usize, found: usize },
+    // The squared amplitudes summed to this instead of 1
+    NotNormalized(f64),
+    InvalidQubitIndex { qubit: usize, len: usize },
+    NonUnitary,
+    EmptySystem,
+    // Only unitary gates have a controlled version, this names the gate that wasn't
+    NotControllable(String),
+    // A Bloch vector this long, past the unit sphere, so its density matrix was wrong
+    OutsideBlochSphere(f64),
+}
+
+impl Display for QuantumError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QuantumError::DimensionMismatch { expected, found } => {
+                write!(f, "dimension mismatch, expected {expected} but found {found}")
+            }
+            QuantumError::NotNormalized(norm) => {
+                write!(f, "state isn't normalized, probabilities sum to {norm}")
+            }
+            QuantumError::InvalidQubitIndex { qubit, len } => {
+                write!(f, "qubit {qubit} out of range for {len} qubits")
+            }
+            QuantumError::NonUnitary => write!(f, "matrix isn't unitary"),
+            QuantumError::EmptySystem => {
+                write!(f, "a QubitSystem needs at least one qubit, got an empty vector")
+            }
+            QuantumError::NotControllable(gate) => {
+                write!(f, "{gate} can't be controlled, only unitary gates can")
+            }
+            QuantumError::OutsideBlochSphere(length) => {
+                write!(f, "Bloch vector has length {length}, more than 1")
+            }
+        }
+    }
+}
+
+impl Error for QuantumError {}
@@ -0,0 +1,242 @@
+use std::f64::consts::FRAC_1_SQRT_2;
+use std::ops::{Add, Mul};
+
+use crate::complex::ComplexNumber;
+use crate::qubit::{Gate, QubitSystem};
+
+// An amplitude of a Clifford+T circuit, held exactly as (a + bω + cω² + dω³) / √2^k
+// with ω = e^(iπ/4). Every such circuit's amplitudes can be written this way, so
+// there's no float error to build up. Always kept with the smallest k, so equal
+// amplitudes compare equal
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ExactAmplitude {
+    coefficients: [i64; 4],
+    k: u32,
+}
+
+impl ExactAmplitude {
+    pub const ZERO: Self = Self { coefficients: [0; 4], k: 0 };
+    pub const ONE: Self = Self { coefficients: [1, 0, 0, 0], k: 0 };
+    // 1/√2, e.g. either amplitude of |+⟩
+    pub const SQRT_HALF: Self = Self { coefficients: [1, 0, 0, 0], k: 1 };
+
+    pub fn new(coefficients: [i64; 4], k: u32) -> Self {
+        Self { coefficients, k }.reduced()
+    }
+
+    // ω^power / √2^k, the form every entry of a Clifford+T gate's matrix takes
+    fn unit(power: usize, k: u32) -> Self {
+        let mut coefficients = [0; 4];
+        coefficients[power % 4] = if power % 8 < 4 { 1 } else { -1 };
+        Self { coefficients, k }
+    }
+
+    // The exact form of a gate matrix entry, if it is one of 0 or ω^j / √2^k for k ≤ 1
+    pub fn from_complex(value: ComplexNumber) -> Option<Self> {
+        if value.approx_eq(&ComplexNumber::real(0.0), 1e-12) {
+            return Some(Self::ZERO);
+        }
+
+        (0..2)
+            .flat_map(|k| (0..8).map(move |power| Self::unit(power, k)))
+            .find(|candidate| candidate.to_complex().approx_eq(&value, 1e-12))
+    }
+
+    pub fn to_complex(&self) -> ComplexNumber {
+        let [a, b, c, d] = self.coefficients.map(|n| n as f64);
+        let odd = if self.k.is_multiple_of(2) { 1.0 } else { FRAC_1_SQRT_2 };
+        let scale = odd * 0.5f64.powi(self.k as i32 / 2);
+
+        // ω = (1 + i)/√2, ω² = i and ω³ = (-1 + i)/√2
+        let real = a + (b - d) * FRAC_1_SQRT_2;
+        let imaginary = c + (b + d) * FRAC_1_SQRT_2;
+        ComplexNumber::new(real * scale, imaginary * scale)
+    }
+
+    // Multiplies the numerator by √2 = ω - ω³
+    fn times_sqrt2(coefficients: [i64; 4]) -> [i64; 4] {
+        let [a, b, c, d] = coefficients;
+        [b - d, a + c, b + d, c - a]
+    }
+
+    // Divides out √2 from the numerator for as long as it stays an integer combination
+    fn reduced(mut self) -> Self {
+        if self.coefficients == [0; 4] {
+            return Self::ZERO;
+        }
+
+        while self.k > 0 {
+            let doubled = Self::times_sqrt2(self.coefficients);
+            if doubled.iter().any(|n| n % 2 != 0) {
+                break;
+            }
+            self.coefficients = doubled.map(|n| n / 2);
+            self.k -= 1;
+        }
+        self
+    }
+}
+
+impl Add for ExactAmplitude {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        let (mut low, mut high) = if self.k <= rhs.k { (self, rhs) } else { (rhs, self) };
+        while low.k < high.k {
+            low.coefficients = Self::times_sqrt2(low.coefficients);
+            low.k += 1;
+        }
+
+        for (sum, n) in high.coefficients.iter_mut().zip(low.coefficients) {
+            *sum += n;
+        }
+        high.reduced()
+    }
+}
+
+// Polynomials in ω, wrapping around with ω⁴ = -1
+impl Mul for ExactAmplitude {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        let mut coefficients = [0; 4];
+        for (i, a) in self.coefficients.iter().enumerate() {
+            for (j, b) in rhs.coefficients.iter().enumerate() {
+                if i + j < 4 {
+                    coefficients[i + j] += a * b;
+                } else {
+                    coefficients[i + j - 4] -= a * b;
+                }
+            }
+        }
+
+        Self { coefficients, k: self.k + rhs.k }.reduced()
+    }
+}
+
+// A state vector of exact amplitudes, for circuits made only of Clifford+T gates
+// (and the permutations and controlled versions of them). Laid out like QubitSystem
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExactSystem {
+    values: Vec<ExactAmplitude>,
+    len: usize,
+}
+
+impl ExactSystem {
+    // |0...0⟩ on num_qubits qubits
+    pub fn new(num_qubits: usize) -> Self {
+        let mut values = vec![ExactAmplitude::ZERO; 1 << num_qubits];
+        values[0] = ExactAmplitude::ONE;
+        Self { values, len: num_qubits }
+    }
+
+    // Runs a whole circuit from |0...0⟩, or None if any gate can't be done exactly
+    pub fn run(gates: &[Vec<Gate>]) -> Option<Self> {
+        let mut system = Self::new(gates.first()?.len());
+
+        for column in gates {
+            for (register, gate) in column.iter().enumerate() {
+                if let Some(matrix) = exact_matrix(gate)? {
+                    system.apply(register, &matrix);
+                }
+            }
+        }
+
+        Some(system)
+    }
+
+    pub fn values(&self) -> &[ExactAmplitude] {
+        &self.values
+    }
+
+    // The same state as an ordinary float QubitSystem
+    pub fn to_system(&self) -> QubitSystem {
+        QubitSystem::from_tensor(self.values.iter().map(|n| n.to_complex()).collect(), self.len)
+    }
+
+    // Applies a gate whose first (most significant) qubit is target, like apply_gate
+    fn apply(&mut self, target: usize, matrix: &[Vec<ExactAmplitude>]) {
+        let size = matrix.len().trailing_zeros() as usize;
+        let shift = self.len - target - size;
+        let mask = (matrix.len() - 1) << shift;
+
+        self.values = (0..self.values.len())
+            .map(|i| {
+                let (row, base) = ((i & mask) >> shift, i & !mask);
+                matrix[row].iter().enumerate().fold(ExactAmplitude::ZERO, |sum, (col, entry)| {
+                    sum + *entry * self.values[base | (col << shift)]
+                })
+            })
+            .collect();
+    }
+}
+
+// The gate's matrix in exact form, Some(None) for cells that do nothing (identities and
+// continuation cells) and None for gates with no exact form. Measurements, asserts,
+// custom gates and anything taking an angle are never exact, even at a nice angle
+pub fn exact_matrix(gate: &Gate) -> Option<Option<Vec<Vec<ExactAmplitude>>>> {
+    match gate {
+        Gate::I => return Some(None),
+        Gate::Other(name) if name == "none" => return Some(None),
+        Gate::M | Gate::MeasureReset | Gate::Assert(_) | Gate::Other(_) => return None,
+        _ if !gate.params().is_empty() => return None,
+        _ => {}
+    }
+
+    let matrix = gate.to_matrix();
+    (0..matrix.len())
+        .map(|i| matrix[i].iter().map(|entry| ExactAmplitude::from_complex(*entry)).collect())
+        .collect::<Option<Vec<Vec<ExactAmplitude>>>>()
+        .map(Some)
+}
+
+// Whether every gate in the circuit can be simulated exactly, see ExactSystem
+pub fn is_clifford_t(gates: &[Vec<Gate>]) -> bool {
+    gates.iter().flatten().all(|gate| exact_matrix(gate).is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gates;
+
+    #[test]
+    pub fn exact_bell() {
+        let gates = gates![[H, I], [CNOT, Other(String::from("none"))]];
+        assert!(is_clifford_t(&gates));
+        let bell = ExactSystem::run(&gates).unwrap();
+
+        let zero = ExactAmplitude::ZERO;
+        let half = ExactAmplitude::SQRT_HALF;
+        assert_eq!(bell.values(), [half, zero, zero, half]);
+        assert_eq!(bell.to_system().get_values()[0].real, FRAC_1_SQRT_2);
+    }
+
+    #[test]
+    pub fn no_drift() {
+        // A thousand and one Hadamards and eight Ts in a row are exactly H and I
+        let mut gates = vec![vec![Gate::H]; 1001];
+        gates.extend(vec![vec![Gate::T]; 8]);
+
+        let system = ExactSystem::run(&gates).unwrap();
+        assert_eq!(system.values(), [ExactAmplitude::SQRT_HALF; 2]);
+    }
+
+    #[test]
+    pub fn exact_arithmetic() {
+        let omega = ExactAmplitude::new([0, 1, 0, 0], 0);
+        let i = ExactAmplitude::new([0, 0, 1, 0], 0);
+        assert_eq!(omega * omega, i);
+        assert_eq!(i * i, ExactAmplitude::new([-1, 0, 0, 0], 0));
+
+        // 1/√2 + 1/√2 = √2 = ω - ω³, and 2/√2² reduces to 1
+        let sqrt2 = ExactAmplitude::SQRT_HALF + ExactAmplitude::SQRT_HALF;
+        assert_eq!(sqrt2, ExactAmplitude::new([0, 1, 0, -1], 0));
+        assert_eq!(ExactAmplitude::new([2, 0, 0, 0], 2), ExactAmplitude::ONE);
+
+        assert!(!is_clifford_t(&[vec![Gate::RX(0.5)]]));
+        assert!(!is_clifford_t(&[vec![Gate::M]]));
+        let controlled_s = Gate::Controlled(Box::new(Gate::S));
+        assert!(is_clifford_t(&[vec![controlled_s, Gate::Other(String::from("none"))]]));
+    }
+}
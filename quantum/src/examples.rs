@@ -0,0 +1,122 @@
+use std::f64::consts::{FRAC_PI_2, FRAC_PI_8};
+
+use crate::circuit::{Circuit, parity_circuit, run_circuit_on};
+use crate::gates;
+use crate::qubit::{Gate, Qubit, QubitSystem};
+
+// A note shown next to one gate of an example as (column, register, note),
+// e.g. a rotation's angle written as a fraction of π
+pub type GateNote = (usize, usize, &'static str);
+
+// Name, gate columns, classical wires (column, measured qubit, conditioned qubit) and notes
+pub type Example = (&'static str, Vec<Vec<Gate>>, Vec<(usize, usize, usize)>, Vec<GateNote>);
+
+// The example circuits bundled with the editor
+pub fn examples() -> Vec<Example> {
+    vec![
+        (
+            "Entanglement",
+            gates![[H, I], [CNOT, Other(String::from("none"))], [I, I]],
+            vec![],
+            vec![],
+        ),
+        (
+            "Partial Entanglement",
+            gates![
+                [H, I],
+                [I, RY(FRAC_PI_8)],
+                [CNOT, Other(String::from("none"))],
+                [I, RY(FRAC_PI_8)],
+                [I, I]
+            ],
+            vec![],
+            vec![(1, 1, "π/8"), (3, 1, "π/8")],
+        ),
+        (
+            "Quantum Teleportation",
+            gates![
+                [X, H, I],
+                [I, CNOT, Other(String::from("none"))],
+                [CNOT, Other(String::from("none")), I],
+                [H, I, I],
+                [I, M, X],
+                [M, I, Z],
+                [I, I, I]
+            ],
+            vec![(4, 1, 2), (5, 0, 2)],
+            vec![],
+        ),
+        (
+            "Deutsch–Jozsa Algorithm",
+            gates![
+                [X, I, I],
+                [H, H, H],
+                [SWAP, Other(String::from("none")), I],
+                [I, CNOT, Other(String::from("none"))],
+                [SWAP, Other(String::from("none")), I],
+                [I, CNOT, Other(String::from("none"))],
+                [H, H, I],
+                [M, M, I]
+            ],
+            vec![],
+            vec![],
+        ),
+        ("Parity", parity_example(), vec![], vec![]),
+        ("Phase Estimation", phase_estimation_example(FRAC_PI_2, 3), vec![], vec![]),
+    ]
+}
+
+// The note on a cell, if there is one
+pub fn gate_note(notes: &[GateNote], column: usize, register: usize) -> Option<&'static str> {
+    notes.iter().find(|note| note.0 == column && note.1 == register).map(|note| note.2)
+}
+
+// Sets two of three inputs and XORs them onto the last qubit, which should read 0
+fn parity_example() -> Vec<Vec<Gate>> {
+    let mut gates = gates![[X, I, X, I]];
+    gates.extend(parity_circuit(&[0, 1, 2], 3));
+    gates.extend(gates![[I, I, I, M], [I, I, I, I]]);
+    gates
+}
+
+// Estimates the phase θ of P(θ) on its |1⟩ eigenstate with a counting register of the
+// given size, which reads θ/2π as a binary fraction, e.g. 010 for θ = π/2 and 3 bits
+pub fn phase_estimation_example(theta: f64, counting: usize) -> Vec<Vec<Gate>> {
+    let qubits: Vec<usize> = (0..counting).collect();
+    let prepared = qubits.iter().fold(Circuit::new().x(counting), |c, q| c.h(*q));
+
+    // Qubit q kicks back the phase of P(θ) applied 2^(counting - 1 - q) times
+    let kicked = qubits
+        .iter()
+        .fold(prepared, |c, q| c.cphase(*q, counting, theta * (1 << (counting - 1 - q)) as f64));
+
+    let transformed = kicked.barrier().qft_inverse(&qubits).barrier();
+    let mut gates = qubits.iter().fold(transformed, |c, q| c.measure(*q)).build();
+    gates.push(vec![Gate::I; counting + 1]);
+    gates
+}
+
+// Teleports source from qubit 0 to qubit 2 with the "Quantum Teleportation" example and
+// checks qubit 2 ends up in the source state, whichever way the measurements went
+pub fn verify_teleportation(source: Qubit) -> bool {
+    let (_, mut gates, wires, _) =
+        examples().into_iter().find(|example| example.0 == "Quantum Teleportation").unwrap();
+    // The example teleports |1⟩ by starting with an X, start from the source instead
+    gates[0][0] = Gate::I;
+
+    let mut system = QubitSystem::new(vec![source.clone(), Qubit::zero(), Qubit::zero()]);
+    run_circuit_on(&mut system, &gates, &wires);
+
+    // Fidelity of qubit 2's reduced state with the source, ⟨ψ|ρ|ψ⟩, summed over the
+    // (already collapsed) values of the first two qubits
+    let source = source.as_vec();
+    let fidelity: f64 = system
+        .get_values()
+        .chunks(2)
+        .map(|pair| {
+            (source[0].conjugate() * pair[0] + source[1].conjugate() * pair[1]).abs_squared()
+        })
+        .sum();
+
+    (fidelity - 1.0).abs() < 1e-6
+}
@@ -1,6 +1,11 @@
 #![feature(more_float_constants)]
 
+pub mod circuit;
 pub mod complex;
+pub mod error;
+pub mod exact;
+pub mod examples;
 pub mod matrix;
 pub mod qubit;
+pub mod simulator;
 pub mod prelude;
@@ -1,12 +1,14 @@
 use crate::c;
 use crate::matrix_new;
 use crate::complex::ComplexNumber;
+use std::f64::consts::PI;
 use std::fmt::Debug;
 use std::ops::Index;
 use std::ops::IndexMut;
 use std::ops::Mul;
+use std::ops::Sub;
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct Matrix {
     value: Vec<Vec<ComplexNumber>>,
     shape: (usize, usize),
@@ -29,6 +31,42 @@ impl Matrix {
         self.value.len()
     }
 
+    // Entry by entry comparison with a chosen tolerance, == allows 0.05
+    pub fn approx_eq(&self, other: &Self, epsilon: f64) -> bool {
+        let mut entries = self.value.iter().flatten().zip(other.value.iter().flatten());
+        self.shape == other.shape && entries.all(|(a, b)| a.approx_eq(b, epsilon))
+    }
+
+    // Whether U†U is the identity to within epsilon in every entry
+    pub fn is_unitary(&self, epsilon: f64) -> bool {
+        let n = self.len();
+        if self.shape != (n, n) {
+            return false;
+        }
+
+        (0..n).all(|i| {
+            (0..n).all(|j| {
+                let entry = (0..n)
+                    .map(|k| self[k][i].conjugate() * self[k][j])
+                    .fold(c!(0.0), |acc, value| acc + value);
+                entry.approx_eq(&c!(if i == j { 1.0 } else { 0.0 }), epsilon)
+            })
+        })
+    }
+
+    // Frobenius norm of self - other, the root of the summed squared entry differences
+    // A simple way to say how close a decomposition is to the gate it's meant to be
+    pub fn distance(&self, other: &Matrix) -> f64 {
+        let difference = self.clone() - other.clone();
+        difference.value.iter().flatten().map(|n| n.abs_squared()).sum::<f64>().sqrt()
+    }
+
+    // Gates side by side, self on the more significant qubits, the way a column is built up
+    // The same as kronecker, named for what it means for gates
+    pub fn tensor(&self, other: &Self) -> Self {
+        self.kronecker(other)
+    }
+
     pub fn kronecker(&self, other: &Self) -> Self {
         let a_rows = self.value.len();
         let a_cols = self[0].len();
@@ -39,6 +77,10 @@ impl Matrix {
 
         for i in 0..a_rows {
             for j in 0..a_cols {
+                // Gate matrices are mostly zeros, whose blocks are already in place
+                if self[i][j].real == 0.0 && self[i][j].imaginary == 0.0 {
+                    continue;
+                }
                 for k in 0..b_rows {
                     for l in 0..b_cols {
                         result[i * b_rows + k][j * b_cols + l] = self[i][j] * other[k][l];
@@ -67,6 +109,32 @@ impl Matrix {
     pub fn identity2() -> Self {
         matrix_new!([c!(1.0), c!(0.0)], [c!(0.0), c!(1.0)])
     }
+
+    // The size x size identity
+    pub fn identity(size: usize) -> Self {
+        let value = (0..size)
+            .map(|i| (0..size).map(|j| c!(if i == j { 1.0 } else { 0.0 })).collect())
+            .collect();
+        Matrix::new(value)
+    }
+
+    // Matrix product self · other, i.e. other is applied first
+    pub fn matmul(&self, other: &Matrix) -> Matrix {
+        assert_eq!(self.shape.1, other.shape.0, "matrix shapes don't match");
+
+        let value = (0..self.shape.0)
+            .map(|i| {
+                (0..other.shape.1)
+                    .map(|j| {
+                        (0..self.shape.1)
+                            .map(|k| self[i][k] * other[k][j])
+                            .fold(c!(0.0), |sum, n| sum + n)
+                    })
+                    .collect()
+            })
+            .collect();
+        Matrix::new(value)
+    }
 }
 
 // One Qubit Gates 
@@ -91,6 +159,21 @@ impl Matrix {
         matrix_new!([c!(1.0), c!(0.0)], [c!(0.0), c!(0.0, 1.0)])
     }
 
+    // Inverse of S, phase shift of -π/2
+    pub fn sdg() -> Self {
+        matrix_new!([c!(1.0), c!(0.0)], [c!(0.0), c!(0.0, -1.0)])
+    }
+
+    // Phase shift of π/4, the square root of S
+    pub fn t() -> Self {
+        Matrix::phase(PI / 4.0)
+    }
+
+    // Inverse of T, phase shift of -π/4
+    pub fn tdg() -> Self {
+        Matrix::phase(-PI / 4.0)
+    }
+
     pub fn phase(theta: f64) -> Self {
         // e^iϕ
         let value = c!(0.0, theta).exp();
@@ -109,19 +192,92 @@ impl Matrix {
         let sin = (theta / 2.0).sin();
         let cos = (theta / 2.0).cos();
 
-        matrix_new!([c!(cos), c!(0.0, sin * -1.0)], [c!(0.0, sin), c!(cos)])
+        matrix_new!([c!(cos), c!(sin * -1.0)], [c!(sin), c!(cos)])
     }
-     
+
+    // diag(e^(iθ/2), e^(-iθ/2)), which is e^(iθ/2)·P(-θ)
+    // This rotates the opposite way to most references, see rz_qiskit
     pub fn rz(theta: f64) -> Self {
         let sin = (theta / 2.0).sin();
         let cos = (theta / 2.0).cos();
 
         matrix_new!([c!(cos, sin), c!(0.0)], [c!(0.0), c!(cos, -1.0 * sin)])
     }
+
+    // RZ as Qiskit and most textbooks define it, diag(e^(-iθ/2), e^(iθ/2))
+    // Up to a global phase of e^(-iθ/2) this is P(θ), so it adds a relative phase of e^(iθ)
+    pub fn rz_qiskit(theta: f64) -> Self {
+        Matrix::rz(-theta)
+    }
+
+    // Rotation by theta about any axis of the Bloch sphere, cos(θ/2)I - i·sin(θ/2)(n·σ)
+    // The axis is normalized first, so it only needs to point the right way, and the zero
+    // vector has no direction so it doesn't rotate at all
+    // The x and y axes give rx and ry, the z axis gives rz_qiskit rather than rz
+    pub fn rotation(axis: [f64; 3], theta: f64) -> Self {
+        let length = axis.iter().map(|n| n * n).sum::<f64>().sqrt();
+        if length == 0.0 {
+            return Matrix::identity2();
+        }
+        let [x, y, z] = axis.map(|n| n / length);
+        let (sin, cos) = (theta / 2.0).sin_cos();
+
+        matrix_new!(
+            [c!(cos, -sin * z), c!(-sin * y, -sin * x)],
+            [c!(sin * y, -sin * x), c!(cos, sin * z)]
+        )
+    }
+
+    // General single qubit rotation U(θ, φ, λ), e.g. U(θ, 0, 0) is RY(θ) and U(0, 0, λ) is P(λ)
+    pub fn u3(theta: f64, phi: f64, lambda: f64) -> Self {
+        let (s, c) = (theta / 2.0).sin_cos();
+        matrix_new!(
+            [c!(c), c!(0.0, lambda).exp() * c!(-s)],
+            [c!(0.0, phi).exp() * c!(s), c!(0.0, phi + lambda).exp() * c!(c)]
+        )
+    }
 }
 
 // Two Qubit Gates
 impl Matrix {
+    // Controlled version of a gate, with one control qubit per entry of control_states
+    // The controls come first (most significant) and the gate's own qubits last
+    // A true control fires on |1⟩ as normal, a false one is an anti-control that fires on |0⟩
+    pub fn controlled_on(gate: &Matrix, control_states: &[bool]) -> Self {
+        let gate_size = gate.len();
+        let size = gate_size << control_states.len();
+        let block = control_states.iter().fold(0, |acc, state| (acc << 1) | *state as usize);
+
+        let mut mat = vec![vec![c!(0.0); size]; size];
+        for (i, row) in mat.iter_mut().enumerate() {
+            if i / gate_size == block {
+                for j in 0..gate_size {
+                    row[block * gate_size + j] = gate[i % gate_size][j];
+                }
+            } else {
+                row[i] = c!(1.0);
+            }
+        }
+
+        Matrix::new(mat)
+    }
+
+    // diag(e^(iθ₀), e^(iθ₁), ...) over as many qubits as there are powers of two of angles
+    pub fn diagonal_phase(phases: &[f64]) -> Self {
+        assert!(
+            phases.len() >= 2 && phases.len().is_power_of_two(),
+            "need a power of two phases, at least two, got {}",
+            phases.len()
+        );
+
+        let mut value = vec![vec![c!(0.0); phases.len()]; phases.len()];
+        for (i, theta) in phases.iter().enumerate() {
+            value[i][i] = c!(0.0, *theta).exp();
+        }
+
+        Matrix::new(value)
+    }
+
     pub fn swap() -> Self {
         matrix_new!(
             [c!(1.0), c!(0.0), c!(0.0), c!(0.0)],
@@ -143,6 +299,17 @@ impl Matrix {
         )
     }
 
+    // Anti-controlled Not, performs a Not on the target if the control is 0
+    pub fn acnot() -> Self {
+        Matrix::controlled_on(&Matrix::pauli_x(), &[false])
+    }
+
+    // Phase gate controlled by the qubit before it, adds e^(iθ) to |11⟩ only
+    // Symmetric in its two qubits, so it doesn't matter which one is the control
+    pub fn cphase(theta: f64) -> Self {
+        Matrix::controlled_on(&Matrix::phase(theta), &[true])
+    }
+
     // Controlled Pauli Z gate
     pub fn cz() -> Self {
         matrix_new!(
@@ -182,29 +349,106 @@ impl Matrix {
         )
     }
 
+    // Flips the sign of |111⟩ only, symmetric in all three qubits
+    pub fn ccz() -> Self {
+        Matrix::controlled_on(&Matrix::pauli_z(), &[true, true])
+    }
+
     pub fn cccx() -> Self {
-        let mut mat = vec![vec![c!(0.0); 16]; 16];
+        Matrix::mcx(3)
+    }
 
-        for x in 0..14 {
-            for y in 0..14 {
-                if x == y {
-                    mat[x][y] = c!(1.0);
-                }
-            }
+    // X on the last qubit controlled by all the ones before it, a 2^(c+1) permutation
+    // that only swaps the two basis states where every control is |1⟩
+    pub fn mcx(num_controls: usize) -> Self {
+        Matrix::controlled_on(&Matrix::pauli_x(), &vec![true; num_controls])
+    }
+}
+
+// Tensor product of single qubit Paulis, e.g. "XZI" is X ⊗ Z ⊗ I
+// The first character acts on qubit 0, the most significant one
+pub fn pauli_string_matrix(s: &str) -> Matrix {
+    assert!(!s.is_empty(), "pauli string must name at least one qubit");
+
+    s.chars()
+        .map(|pauli| match pauli.to_ascii_uppercase() {
+            'I' => Matrix::identity2(),
+            'X' => Matrix::pauli_x(),
+            'Y' => Matrix::pauli_y(),
+            'Z' => Matrix::pauli_z(),
+            other => panic!("{other} is not a pauli, expected one of I, X, Y or Z"),
+        })
+        .reduce(|acc, pauli| acc.kronecker(&pauli))
+        .unwrap()
+}
+
+// Eigenvalues and orthonormal eigenvectors of a real symmetric matrix, using cyclic
+// Jacobi rotations. vectors[k] is the eigenvector for values[k], in no particular order
+pub fn symmetric_eigen(mut a: Vec<Vec<f64>>) -> (Vec<f64>, Vec<Vec<f64>>) {
+    let n = a.len();
+    let mut v: Vec<Vec<f64>> =
+        (0..n).map(|i| (0..n).map(|j| if i == j { 1.0 } else { 0.0 }).collect()).collect();
+
+    for _ in 0..100 {
+        let off_diagonal: f64 =
+            (0..n).map(|i| (i + 1..n).map(|j| a[i][j].powi(2)).sum::<f64>()).sum();
+        if off_diagonal < 1e-24 {
+            break;
         }
 
-        mat[14][15] = c!(1.0);
-        mat[15][14]= c!(1.0);
+        for p in 0..n {
+            for q in p + 1..n {
+                if a[p][q] == 0.0 {
+                    continue;
+                }
+
+                // Rotation in the p, q plane that zeroes a[p][q]
+                let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let cos = 1.0 / (t * t + 1.0).sqrt();
+                let sin = t * cos;
 
-        Matrix::new(mat)
+                for row in a.iter_mut().chain(v.iter_mut()) {
+                    let (kp, kq) = (row[p], row[q]);
+                    row[p] = cos * kp - sin * kq;
+                    row[q] = sin * kp + cos * kq;
+                }
+                let (upper, lower) = a.split_at_mut(q);
+                for (pk, qk) in upper[p].iter_mut().zip(lower[0].iter_mut()) {
+                    (*pk, *qk) = (cos * *pk - sin * *qk, sin * *pk + cos * *qk);
+                }
+            }
+        }
     }
+
+    let values = (0..n).map(|i| a[i][i]).collect();
+    let vectors = (0..n).map(|k| v.iter().map(|row| row[k]).collect()).collect();
+    (values, vectors)
 }
 
+// Matrix product, so a * b applies b first. Gates side by side are a.tensor(&b)
 impl Mul for Matrix {
     type Output = Matrix;
 
     fn mul(self, rhs: Self) -> Self::Output {
-        self.kronecker(&rhs)
+        self.matmul(&rhs)
+    }
+}
+
+// Entry by entry difference, both matrices must be the same shape
+impl Sub for Matrix {
+    type Output = Matrix;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        assert_eq!(self.shape, rhs.shape, "can't subtract matrices of different shapes");
+        let value = self
+            .value
+            .into_iter()
+            .zip(rhs.value)
+            .map(|(a, b)| a.into_iter().zip(b).map(|(x, y)| x - y).collect())
+            .collect();
+
+        Self { value, shape: self.shape }
     }
 }
 
@@ -245,3 +489,186 @@ macro_rules! matrix_new {
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qubit::{Qubit, QubitSystem};
+
+    #[test]
+    pub fn pauli_strings() {
+        assert_eq!(pauli_string_matrix("X"), Matrix::pauli_x());
+        assert_eq!(pauli_string_matrix("zi"), Matrix::pauli_z().kronecker(&Matrix::identity2()));
+        assert_eq!(pauli_string_matrix("XYZ").len(), 8);
+    }
+
+    #[test]
+    pub fn distances() {
+        assert_eq!(Matrix::hadamard().distance(&Matrix::hadamard()), 0.0);
+        assert!((Matrix::pauli_x().distance(&Matrix::identity2()) - 2.0).abs() < 1e-12);
+
+        // Equal up to float error, while a global phase still counts as a difference
+        let about_z = Matrix::rotation([0.0, 0.0, 2.0], 0.3);
+        assert!(about_z.distance(&Matrix::rz_qiskit(0.3)) < 1e-6);
+        assert!(Matrix::rz(0.3).distance(&Matrix::phase(-0.3)) > 0.1);
+    }
+
+    #[test]
+    pub fn tensor_and_product() {
+        let tensored = Matrix::hadamard().tensor(&Matrix::pauli_x());
+        assert_eq!(tensored.len(), 4);
+        assert!(tensored.approx_eq(&Matrix::hadamard().kronecker(&Matrix::pauli_x()), 1e-12));
+
+        // H·H = I, and XZ = -iY
+        assert!((Matrix::hadamard() * Matrix::hadamard()).approx_eq(&Matrix::identity(2), 1e-12));
+        let xz = Matrix::pauli_x() * Matrix::pauli_z();
+        assert!(xz.approx_eq(&Matrix::pauli_y().scale(c!(0.0, -1.0)), 1e-12));
+    }
+
+    #[test]
+    pub fn symmetric_eigenvectors() {
+        let matrix = vec![vec![2.0, 1.0, 0.0], vec![1.0, 2.0, 1.0], vec![0.0, 1.0, 2.0]];
+        let (values, vectors) = symmetric_eigen(matrix.clone());
+
+        for (value, vector) in values.iter().zip(&vectors) {
+            for (row, component) in matrix.iter().zip(vector) {
+                let applied: f64 = row.iter().zip(vector).map(|(a, b)| a * b).sum();
+                assert!((applied - value * component).abs() < 1e-9);
+            }
+            assert!((vector.iter().map(|x| x * x).sum::<f64>() - 1.0).abs() < 1e-9);
+        }
+
+        let mut values = values;
+        values.sort_by(f64::total_cmp);
+        assert!((values[0] - (2.0 - 2f64.sqrt())).abs() < 1e-9);
+        assert!((values[2] - (2.0 + 2f64.sqrt())).abs() < 1e-9);
+    }
+
+    #[test]
+    pub fn rz_conventions() {
+        let plus = || QubitSystem::new(vec![Qubit::zero().hadamard()]);
+        let (mut ours, mut qiskit, mut phase) = (plus(), plus(), plus());
+        ours.apply_gate(0, Matrix::rz(0.8));
+        qiskit.apply_gate(0, Matrix::rz_qiskit(0.8));
+        phase.apply_gate(0, Matrix::phase(0.8));
+
+        // Same measurement statistics, opposite relative phase
+        for (a, b) in ours.get_values().iter().zip(qiskit.get_values()) {
+            assert!((a.abs_squared() - b.abs_squared()).abs() < 1e-9);
+        }
+        assert!(ours.get_values() != qiskit.get_values());
+        assert!(ours.fidelity(&qiskit) < 0.9);
+
+        // The Qiskit convention only differs from P(θ) by a global phase
+        assert!((qiskit.fidelity(&phase) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    pub fn tolerances() {
+        // Exact identities hold to far tighter than the default tolerance
+        let cnot = Matrix::controlled_on(&Matrix::pauli_x(), &[true]);
+        assert!(cnot.approx_eq(&Matrix::cnot(), 1e-12));
+        assert!(Matrix::rz_qiskit(0.3).approx_eq(&Matrix::rz(-0.3), 1e-12));
+
+        // == can't tell these apart, a tight tolerance can
+        assert!(Matrix::rx(1.0) == Matrix::rx(1.02));
+        assert!(!Matrix::rx(1.0).approx_eq(&Matrix::rx(1.02), 1e-3));
+
+        // And a loose one accepts what == rejects
+        assert!(Matrix::rx(1.0) != Matrix::rx(1.2));
+        assert!(Matrix::rx(1.0).approx_eq(&Matrix::rx(1.2), 0.15));
+
+        assert!(!Matrix::cnot().approx_eq(&Matrix::pauli_x(), 1.0));
+    }
+
+    #[test]
+    pub fn unitarity() {
+        assert!(Matrix::hadamard().is_unitary(1e-9));
+        assert!(Matrix::rz(0.3).kronecker(&Matrix::cnot()).is_unitary(1e-9));
+        assert!(!Matrix::hadamard().scale(c!(2.0)).is_unitary(1e-9));
+        assert!(!matrix_new!([c!(1.0), c!(1.0)]).is_unitary(1e-9));
+    }
+
+    #[test]
+    pub fn axis_rotations() {
+        for theta in [0.0, 0.4, PI / 2.0, 2.5] {
+            assert!(Matrix::rotation([1.0, 0.0, 0.0], theta).approx_eq(&Matrix::rx(theta), 1e-12));
+            assert!(Matrix::rotation([0.0, 1.0, 0.0], theta).approx_eq(&Matrix::ry(theta), 1e-12));
+            let z = Matrix::rotation([0.0, 0.0, 1.0], theta);
+            assert!(z.approx_eq(&Matrix::rz_qiskit(theta), 1e-12));
+        }
+
+        // Axes don't have to be unit length
+        let long = Matrix::rotation([0.0, 3.0, 0.0], 0.7);
+        assert!(long.approx_eq(&Matrix::ry(0.7), 1e-12));
+        assert!(Matrix::rotation([1.0, -2.0, 0.5], 1.3).is_unitary(1e-12));
+        assert_eq!(Matrix::rotation([0.0, 0.0, 0.0], 1.3), Matrix::identity2());
+    }
+
+    #[test]
+    pub fn general_rotation() {
+        assert!(Matrix::u3(0.7, 0.0, 0.0).approx_eq(&Matrix::ry(0.7), 1e-12));
+        assert!(Matrix::u3(0.0, 0.0, 0.4).approx_eq(&Matrix::phase(0.4), 1e-12));
+        assert!(Matrix::u3(PI / 2.0, 0.0, PI).approx_eq(&Matrix::hadamard(), 1e-12));
+        assert!(Matrix::u3(0.3, 1.1, -0.6).is_unitary(1e-12));
+    }
+
+    #[test]
+    pub fn controlled() {
+        assert_eq!(Matrix::controlled_on(&Matrix::pauli_x(), &[true]), Matrix::cnot());
+        assert_eq!(Matrix::controlled_on(&Matrix::pauli_z(), &[true]), Matrix::cz());
+        assert_eq!(Matrix::controlled_on(&Matrix::pauli_x(), &[true, true]), Matrix::ccx());
+        assert_eq!(Matrix::controlled_on(&Matrix::swap(), &[true]), Matrix::cswap());
+    }
+
+    #[test]
+    pub fn multi_controlled_x() {
+        assert_eq!(Matrix::mcx(0), Matrix::pauli_x());
+        assert_eq!(Matrix::mcx(1), Matrix::cnot());
+        assert_eq!(Matrix::mcx(2), Matrix::ccx());
+
+        // The identity, except |1110⟩ and |1111⟩ swap
+        let mcx = Matrix::mcx(3);
+        assert_eq!(mcx.len(), 16);
+        for i in 0..16 {
+            for j in 0..16 {
+                let swapped = i >= 14 && j >= 14;
+                let expected = if swapped { i != j } else { i == j };
+                assert_eq!(mcx[i][j], c!(if expected { 1.0 } else { 0.0 }), "({i}, {j})");
+            }
+        }
+    }
+
+    #[test]
+    pub fn cccx_basis_states() {
+        let cccx = Matrix::cccx();
+
+        for state in 0..16 {
+            let mut basis = vec![c!(0.0); 16];
+            basis[state] = c!(1.0);
+            let output = cccx.dot(&basis);
+
+            // Only |1110⟩ (14) and |1111⟩ (15) have all three controls set
+            let expected = match state {
+                14 => 15,
+                15 => 14,
+                other => other,
+            };
+            let position: Vec<usize> = (0..16).filter(|i| output[*i] == c!(1.0)).collect();
+            assert_eq!(position, vec![expected], "|{state:04b}⟩");
+        }
+    }
+
+    #[test]
+    pub fn anti_controlled() {
+        let anti_cnot = Matrix::controlled_on(&Matrix::pauli_x(), &[false]);
+
+        let mut system = QubitSystem::new(vec![Qubit::zero(), Qubit::zero()]);
+        system.apply_full_gate(anti_cnot.clone());
+        assert_eq!(system.measure(), vec![0, 1]);
+
+        let mut system = QubitSystem::new(vec![Qubit::one(), Qubit::zero()]);
+        system.apply_full_gate(anti_cnot);
+        assert_eq!(system.measure(), vec![1, 0]);
+    }
+}
@@ -1,5 +1,7 @@
 pub use crate::matrix::Matrix;
 pub use crate::complex::ComplexNumber;
-pub use crate::qubit::{Qubit, QubitSystem, Gate};
+pub use crate::error::QuantumError;
+pub use crate::qubit::{Basis, Qubit, QubitSystem, Gate};
+pub use crate::simulator::Simulator;
 
-pub use crate::{c, matrix_new};
+pub use crate::{c, gates, matrix_new};
@@ -1,12 +1,18 @@
-use rand::Rng;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use serde::{Deserialize, Serialize};
 use std::{
+    cell::RefCell,
+    collections::HashMap,
+    mem::{Discriminant, discriminant},
+    rc::Rc,
     f64::consts::{PI, SQRT_3},
     fmt::{Debug, Display},
     ops::Not,
 };
 
 use crate::complex::ComplexNumber;
-use crate::matrix::Matrix;
+use crate::error::QuantumError;
+use crate::matrix::{Matrix, pauli_string_matrix, symmetric_eigen};
 use crate::{c, matrix_new};
 
 const SQRT_THIRD: f64 = 1.0 / SQRT_3;
@@ -34,6 +40,14 @@ impl Qubit {
         Qubit::new(ComplexNumber::real(1.0), ComplexNumber::real(0.0))
     }
 
+    // A uniformly random pure state, i.e. a uniform point on the Bloch sphere
+    pub fn random(rng: &mut impl Rng) -> Self {
+        let theta = (1.0 - 2.0 * rng.r#gen::<f64>()).acos();
+        let phi = 2.0 * PI * rng.r#gen::<f64>();
+
+        Qubit::new(c!((theta / 2.0).cos()), c!(0.0, phi).exp() * c!((theta / 2.0).sin()))
+    }
+
     // Checks to see if the given Qubit is valid
     // |a|^2 + |b|^2 = 1 for a normal (valid) qubit
     pub fn is_normal(&self) -> bool {
@@ -96,6 +110,15 @@ impl Qubit {
     pub fn as_vec(&self) -> Vec<ComplexNumber> {
         vec![self.a, self.b]
     }
+
+    // Expectation value ⟨ψ|O|ψ⟩ of a 2x2 Hermitian observable on just this qubit
+    pub fn expectation(&self, observable: &Matrix) -> f64 {
+        assert_eq!(observable.len(), 2, "a single qubit observable is 2x2");
+
+        let a = observable[0][0] * self.a + observable[0][1] * self.b;
+        let b = observable[1][0] * self.a + observable[1][1] * self.b;
+        (self.a.conjugate() * a + self.b.conjugate() * b).real
+    }
 }
 
 impl PartialEq for Qubit {
@@ -110,6 +133,29 @@ impl Debug for Qubit {
     }
 }
 
+// Lists every basis state with a nonzero amplitude, e.g. "1/√2 |00⟩, 1/√2 |11⟩"
+// Basis states less likely than this are left out when a state is written as kets
+// Kept separate from ComplexNumber's == tolerance, which would hide amplitudes up to 0.05
+pub const DISPLAY_THRESHOLD: f64 = 1e-10;
+
+impl Display for QubitSystem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kets: Vec<String> = self
+            .values
+            .iter()
+            .enumerate()
+            .filter(|(_, value)| value.abs_squared() > DISPLAY_THRESHOLD)
+            .map(|(idx, value)| {
+                let bits: String =
+                    index_to_bits(idx, self.len).iter().map(|b| b.to_string()).collect();
+                format!("{} |{}⟩", value.pretty(), bits)
+            })
+            .collect();
+
+        f.write_str(&kets.join(", "))
+    }
+}
+
 impl Not for Qubit {
     type Output = Self;
 
@@ -118,19 +164,74 @@ impl Not for Qubit {
     }
 }
 
+// Axis of the Bloch sphere a qubit is measured along
+// Outcome 0 is the + eigenstate (|0⟩, |+⟩ or |+i⟩) and 1 the - one
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Basis {
+    X,
+    Y,
+    Z,
+}
+
+#[derive(Clone)]
 pub struct QubitSystem {
     values: Vec<ComplexNumber>,
     len: usize,
+    global_phase: ComplexNumber,
+    // Seeded generator for measurements, the thread's RNG is used when there isn't one
+    rng: Option<RefCell<StdRng>>,
 }
 
 impl QubitSystem {
     // Creates a Qubit system, allowing for multi-qubit operations
+    // A system needs at least one qubit, an empty vector panics
     pub fn new(qubits: Vec<Qubit>) -> Self {
+        Self::try_new(qubits).unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    // QubitSystem::new with its own seeded RNG, so measuring and sampling repeat exactly
+    // for the same seed. Clones carry on from the same point of the sequence
+    pub fn new_seeded(qubits: Vec<Qubit>, seed: u64) -> Self {
+        let mut system = Self::new(qubits);
+        system.rng = Some(RefCell::new(StdRng::seed_from_u64(seed)));
+        system
+    }
+
+    // QubitSystem::new, renormalizing afterwards so small per-qubit norm errors
+    // (which the tensor product compounds) don't leave the state slightly off
+    pub fn new_normalized(qubits: Vec<Qubit>) -> Self {
+        let mut system = Self::new(qubits);
+        system.renormalize();
+        system
+    }
+
+    pub fn try_new(qubits: Vec<Qubit>) -> Result<Self, QuantumError> {
         let len = qubits.len();
-        let values =
-            qubits.into_iter().map(|q| q.as_vec()).reduce(|acc, e| tensor_product(acc, e)).unwrap();
+        let values = qubits
+            .into_iter()
+            .map(|q| q.as_vec())
+            .reduce(tensor_product)
+            .ok_or(QuantumError::EmptySystem)?;
+
+        Ok(QubitSystem { values, len, global_phase: c!(1.0), rng: None })
+    }
 
-        QubitSystem { values, len }
+    // Replaces the whole state vector, which must have 2^n normalized amplitudes
+    pub fn set_values(&mut self, values: Vec<ComplexNumber>) -> Result<(), QuantumError> {
+        if values.len() != self.values.len() {
+            return Err(QuantumError::DimensionMismatch {
+                expected: self.values.len(),
+                found: values.len(),
+            });
+        }
+
+        let norm = values.iter().map(|c| c.abs_squared()).sum::<f64>();
+        if (norm - 1.0).abs() > 1e-6 {
+            return Err(QuantumError::NotNormalized(norm));
+        }
+
+        self.values = values;
+        Ok(())
     }
 
     pub fn add_qubit(&mut self, qubit: Qubit) {
@@ -139,7 +240,82 @@ impl QubitSystem {
     }
 
     pub fn from_tensor(values: Vec<ComplexNumber>, len: usize) -> Self {
-        Self { values, len }
+        Self { values, len, global_phase: c!(1.0), rng: None }
+    }
+
+    // Prepares a state with the given real amplitudes using only (controlled) RY rotations
+    // Qubit k is rotated once for every value of the qubits before it, splitting each
+    // branch's weight between its two halves. The amplitudes are normalized first and
+    // there must be a power of two of them, at least two
+    pub fn prepare_real(amplitudes: &[f64]) -> Self {
+        assert!(
+            amplitudes.len() >= 2 && amplitudes.len().is_power_of_two(),
+            "need a power of two amplitudes, got {}",
+            amplitudes.len()
+        );
+        let norm = amplitudes.iter().map(|a| a * a).sum::<f64>().sqrt();
+        assert!(norm > 0.0, "amplitudes can't all be zero");
+        let amplitudes: Vec<f64> = amplitudes.iter().map(|a| a / norm).collect();
+
+        let len = amplitudes.len().trailing_zeros() as usize;
+        let mut system = QubitSystem::new(vec![Qubit::zero(); len]);
+        let weight = |values: &[f64]| values.iter().map(|a| a * a).sum::<f64>().sqrt();
+
+        for qubit in 0..len {
+            let block = 1 << (len - qubit);
+            let targets: Vec<usize> = (0..=qubit).collect();
+
+            for (prefix, values) in amplitudes.chunks(block).enumerate() {
+                let (left, right) = values.split_at(block / 2);
+                // The last qubit keeps the signs, every earlier one only splits magnitudes
+                let (zero, one) = if block == 2 {
+                    (left[0], right[0])
+                } else {
+                    (weight(left), weight(right))
+                };
+                if zero == 0.0 && one == 0.0 {
+                    continue;
+                }
+
+                let rotation = Matrix::ry(2.0 * one.atan2(zero));
+                let controls: Vec<bool> =
+                    (0..qubit).map(|i| (prefix >> (qubit - 1 - i)) & 1 == 1).collect();
+                system.apply_to(&targets, &Matrix::controlled_on(&rotation, &controls));
+            }
+        }
+
+        system
+    }
+
+    pub fn num_qubits(&self) -> usize {
+        self.len
+    }
+
+    // Index into the state vector of a basis state given as one bit per qubit
+    // Qubits are big-endian throughout: qubit 0 is the most significant bit,
+    // matching the order qubits are tensored together in new, so |001⟩ is index 1
+    pub fn index_of_basis(&self, bits: &[usize]) -> usize {
+        debug_assert_eq!(bits.len(), self.len, "need one bit per qubit");
+        debug_assert!(bits.iter().all(|b| *b <= 1), "bits must be 0 or 1");
+
+        bits.iter().fold(0, |acc, bit| (acc << 1) | bit)
+    }
+
+    // Complex amplitude of a basis state given as one bit per qubit, see index_of_basis
+    pub fn amplitude(&self, basis: &[usize]) -> ComplexNumber {
+        self.values[self.index_of_basis(basis)]
+    }
+
+    // Probability of reading the basis state if every qubit were measured
+    pub fn probability(&self, basis: &[usize]) -> f64 {
+        self.amplitude(basis).abs_squared()
+    }
+
+    // Mask of the bit a qubit occupies in a basis state index, see index_of_basis
+    fn qubit_mask(&self, qubit: usize) -> usize {
+        debug_assert!(qubit < self.len, "qubit {qubit} out of range");
+
+        1 << (self.len - 1 - qubit)
     }
 
     // Calclates if a system is normal
@@ -148,18 +324,46 @@ impl QubitSystem {
         self.values.iter().map(|c| c.abs_squared()).sum::<f64>() - 1.0 < 0.05
     }
 
+    // Applies a gate whose first (most significant) qubit is target
+    // Multi-qubit gates act on target and the qubits directly after it
     pub fn apply_gate(&mut self, target: usize, matrix: Matrix) {
+        if let Err(error) = self.try_apply_gate(target, matrix) {
+            panic!("{error}");
+        }
+    }
+
+    // apply_gate, but a target past the end or a gate hanging off the last qubit is an error
+    pub fn try_apply_gate(&mut self, target: usize, matrix: Matrix) -> Result<(), QuantumError> {
+        if target >= self.len {
+            return Err(QuantumError::InvalidQubitIndex { qubit: target, len: self.len });
+        }
+        let size = matrix.len().trailing_zeros() as usize;
+        if !matrix.len().is_power_of_two() || target + size > self.len {
+            return Err(QuantumError::DimensionMismatch {
+                expected: 1 << (self.len - target),
+                found: matrix.len(),
+            });
+        }
+
         let mut full_gate = matrix_new!([c!(1.0)]);
 
-        let mut gate_size = 1;
-        while gate_size < self.values.len() {
-            let partial_gate =
-                if gate_size / 2 == target { matrix.clone() } else { Matrix::identity2() };
-            full_gate = full_gate.kronecker(&partial_gate);
-            gate_size *= partial_gate.len();
+        let mut qubit = 0;
+        while qubit < self.len {
+            let partial_gate = if qubit == target { matrix.clone() } else { Matrix::identity2() };
+            full_gate = full_gate.tensor(&partial_gate);
+            qubit += partial_gate.len().trailing_zeros() as usize;
         }
 
         self.values = full_gate.dot(&self.values);
+        Ok(())
+    }
+
+    // Applies the gate only if a classical condition holds
+    // Used for feed-forward corrections, e.g. after a mid-circuit measurement
+    pub fn apply_if(&mut self, condition: bool, target: usize, matrix: Matrix) {
+        if condition {
+            self.apply_gate(target, matrix);
+        }
     }
 
     pub fn apply_full_gate(&mut self, matrix: Matrix) {
@@ -168,49 +372,240 @@ impl QubitSystem {
         self.values = matrix.dot(&self.values);
     }
 
+    // apply_full_gate, also checking the matrix is unitary so the state stays normalized
+    pub fn try_apply_full_gate(&mut self, matrix: Matrix) -> Result<(), QuantumError> {
+        if matrix.len() != self.values.len() {
+            return Err(QuantumError::DimensionMismatch {
+                expected: self.values.len(),
+                found: matrix.len(),
+            });
+        }
+        if !matrix.is_unitary(1e-6) {
+            return Err(QuantumError::NonUnitary);
+        }
+
+        self.values = matrix.dot(&self.values);
+        Ok(())
+    }
+
+    // Applies the same single qubit gate to every qubit, one in place pass per qubit
+    // O(n·2^n) rather than building the 2^n x 2^n tensor power of the gate
     pub fn apply_gate_all(&mut self, matrix: Matrix) {
         assert!(matrix.len() == 2);
 
-        let mut full_gate = matrix_new!([c!(1.0)]);
+        for qubit in 0..self.len {
+            self.apply_single(qubit, &matrix);
+        }
+    }
+
+    // Applies a 2x2 gate to one qubit in place
+    // Amplitudes are paired up by whether the target bit is 0 or 1, so this is O(2^n)
+    // rather than building the full 2^n x 2^n operator
+    pub fn apply_single(&mut self, target: usize, matrix: &Matrix) {
+        let bit = self.qubit_mask(target);
+
+        for idx in 0..self.values.len() {
+            if idx & bit != 0 {
+                continue;
+            }
 
-        for i in 0..self.len {
-            full_gate = full_gate * matrix.clone();
+            let zero = self.values[idx];
+            let one = self.values[idx | bit];
+            self.values[idx] = matrix[0][0] * zero + matrix[0][1] * one;
+            self.values[idx | bit] = matrix[1][0] * zero + matrix[1][1] * one;
         }
+    }
 
-        self.values = full_gate.dot(&self.values);
+    // Applies a single qubit gate to target only where the control qubit is |1⟩, in place
+    // Control and target can be any two different qubits, in either order
+    pub fn apply_controlled(&mut self, control: usize, target: usize, gate: &Matrix) {
+        assert_ne!(control, target, "control and target must be different qubits");
+        let control_bit = self.qubit_mask(control);
+        let bit = self.qubit_mask(target);
+
+        for idx in 0..self.values.len() {
+            if idx & bit != 0 || idx & control_bit == 0 {
+                continue;
+            }
+
+            let zero = self.values[idx];
+            let one = self.values[idx | bit];
+            self.values[idx] = gate[0][0] * zero + gate[0][1] * one;
+            self.values[idx | bit] = gate[1][0] * zero + gate[1][1] * one;
+        }
     }
 
-    pub fn measure(&mut self) -> Vec<usize> {
-        let probabilities: Vec<f64> = self.values.iter().map(|c| c.abs_squared()).collect();
+    // Swaps qubits a and b where the control qubit is |1⟩ (a Fredkin gate), in place
+    // The three qubits can be anywhere in the system as long as they're distinct
+    pub fn apply_cswap(&mut self, control: usize, a: usize, b: usize) {
+        assert!(
+            control != a && control != b && a != b,
+            "control and swapped qubits must be distinct"
+        );
+        let control_bit = self.qubit_mask(control);
+        let a_bit = self.qubit_mask(a);
+        let b_bit = self.qubit_mask(b);
+
+        // Only |..1..1..0..⟩ and |..1..0..1..⟩ pairs change, visit each pair once
+        for idx in 0..self.values.len() {
+            if idx & control_bit != 0 && idx & a_bit != 0 && idx & b_bit == 0 {
+                self.values.swap(idx, idx ^ a_bit ^ b_bit);
+            }
+        }
+    }
 
-        // Probabilities need to add to one or else the system is corrupted
-        assert!(probabilities.iter().sum::<f64>() - 1.0 < 0.05);
+    // Applies a gate spanning several qubits in place, the qubits don't need to be adjacent
+    // The first target is the most significant qubit of the gate's matrix
+    pub fn apply_to(&mut self, targets: &[usize], matrix: &Matrix) {
+        assert_eq!(matrix.len(), 1 << targets.len());
+
+        let bits: Vec<usize> = targets.iter().map(|t| self.qubit_mask(*t)).collect();
+        let mask = bits.iter().fold(0, |acc, bit| acc | bit);
+
+        // Offset of each of the gate's local basis states within the full state
+        let offsets: Vec<usize> = (0..matrix.len())
+            .map(|local| {
+                bits.iter()
+                    .enumerate()
+                    .filter(|(i, _)| (local >> (targets.len() - 1 - i)) & 1 == 1)
+                    .fold(0, |acc, (_, bit)| acc | bit)
+            })
+            .collect();
+
+        let mut local = vec![c!(0.0); matrix.len()];
+        for base in 0..self.values.len() {
+            if base & mask != 0 {
+                continue;
+            }
 
-        let rand_state = rand::random::<f64>();
-        let mut state = 0;
+            for (value, offset) in local.iter_mut().zip(&offsets) {
+                *value = self.values[base | offset];
+            }
+
+            for (row, offset) in offsets.iter().enumerate() {
+                let mut sum = c!(0.0);
+                for (column, value) in local.iter().enumerate() {
+                    sum += matrix[row][column] * *value;
+                }
+                self.values[base | offset] = sum;
+            }
+        }
+    }
+
+    // Same result as apply_gates, but every gate is applied in place on its own qubits
+    // so the full column operator is never built
+    pub fn apply_column_fast(&mut self, gates: &[Gate]) {
+        for (idx, gate) in gates.iter().enumerate() {
+            match gate {
+                Gate::I | Gate::Assert(_) | Gate::Other(_) => continue,
+                Gate::M => {
+                    self.measure_single(idx);
+                }
+                Gate::MeasureReset => {
+                    self.measure_reset(idx);
+                }
+                gate if gate.num_qubits() == 1 => self.apply_single(idx, &gate.cached_matrix()),
+                gate => {
+                    let targets: Vec<usize> = (idx..idx + gate.num_qubits()).collect();
+                    self.apply_to(&targets, &gate.cached_matrix());
+                }
+            }
+            self.global_phase = self.global_phase * gate.global_phase();
+        }
+    }
 
-        let mut weight = 0.0;
-        for (idx, probability) in probabilities.iter().enumerate() {
-            weight += probability;
-            if rand_state <= weight {
-                state = idx;
-                break;
+    // Applies a gate to the given qubits, the first target is the gate's top qubit
+    // Measurement gates measure their target, identities and custom gates do nothing
+    pub fn apply(&mut self, gate: &Gate, targets: &[usize]) {
+        match gate {
+            Gate::I | Gate::Assert(_) | Gate::Other(_) => return,
+            Gate::M => {
+                self.measure_single(targets[0]);
+            }
+            Gate::MeasureReset => {
+                self.measure_reset(targets[0]);
+            }
+            _ => {
+                assert_eq!(
+                    targets.len(),
+                    gate.num_qubits(),
+                    "{gate:?} acts on {} qubits but {} targets were given",
+                    gate.num_qubits(),
+                    targets.len()
+                );
+
+                if targets.len() == 1 {
+                    self.apply_single(targets[0], &gate.cached_matrix());
+                } else {
+                    self.apply_to(targets, &gate.cached_matrix());
+                }
             }
         }
+        self.global_phase = self.global_phase * gate.global_phase();
+    }
+
+    // Applies a two qubit gate to each (first, second) pair in turn, e.g. a chain of CNOTs
+    pub fn apply_layer(&mut self, gate: &Gate, pairs: &[(usize, usize)]) {
+        for (first, second) in pairs {
+            self.apply(gate, &[*first, *second]);
+        }
+    }
 
-        let mut result = vec![];
-        let bit_size = std::mem::size_of::<usize>() * 8;
+    // Product of the global phase factors of every gate applied through apply_gates or
+    // apply_column_fast. The amplitudes already include it, this just keeps track of how
+    // much of the state's overall phase came from gates like RZ
+    pub fn get_global_phase(&self) -> ComplexNumber {
+        self.global_phase
+    }
 
-        for i in (0..self.len).rev() {
-            // Get bit for given qubit's measured state
-            result.push((state >> i) & 1);
+    // Whether the system is certainly in the basis state written as a ket like "|01⟩" or "01"
+    // Checked without measuring, a ket of the wrong length or with other characters never matches
+    pub fn matches_ket(&self, ket: &str) -> bool {
+        let bits: Vec<usize> = ket
+            .trim_matches(|c| c == '|' || c == '⟩' || c == '>')
+            .chars()
+            .map(|c| match c {
+                '0' => Some(0),
+                '1' => Some(1),
+                _ => None,
+            })
+            .collect::<Option<Vec<usize>>>()
+            .unwrap_or_default();
+
+        if bits.len() != self.len {
+            return false;
         }
 
+        (self.probabilities()[self.index_of_basis(&bits)] - 1.0).abs() < 1e-9
+    }
+
+    // Probability of each basis state, without measuring anything
+    pub fn probabilities(&self) -> Vec<f64> {
+        self.values.iter().map(|c| c.abs_squared()).collect()
+    }
+
+    // Probability of one basis state given by its index, e.g. 0b101 for |101⟩
+    // probability takes the same state as one bit per qubit instead
+    pub fn index_probability(&self, state: usize) -> f64 {
+        self.values[state].abs_squared()
+    }
+
+    pub fn measure(&mut self) -> Vec<usize> {
+        let probabilities = self.probabilities();
+
+        // Probabilities need to add to one or else the system is corrupted
+        assert!(probabilities.iter().sum::<f64>() - 1.0 < 0.05);
+
+        let rand_state = self.random();
+        let state = select_state(&probabilities, rand_state);
+
+        let result = index_to_bits(state, self.len);
+
         let measured_tensor = result
             .iter()
             .map(|e| if *e == 1 { Qubit::one() } else { Qubit::zero() })
             .map(|q| q.as_vec())
-            .reduce(|acc, e| tensor_product(acc, e))
+            .reduce(tensor_product)
             .unwrap();
 
         self.values = measured_tensor;
@@ -218,37 +613,179 @@ impl QubitSystem {
         result
     }
 
-    pub fn measure_single(&mut self, target: usize) -> usize {
-        let modulo = (2 as usize).pow((self.len - target) as u32);
-        let constraint = modulo / 2;
-        let probability_one = self
+    // Simulates measuring many copies of the system, without collapsing this one
+    // Returns how many times each outcome came up
+    pub fn sample(&self, shots: usize) -> HashMap<Vec<usize>, usize> {
+        let probabilities = self.probabilities();
+        let mut counts = HashMap::new();
+
+        for _ in 0..shots {
+            let state = select_state(&probabilities, self.random());
+            *counts.entry(index_to_bits(state, self.len)).or_insert(0) += 1;
+        }
+
+        counts
+    }
+
+    // Uniform in [0, 1), from the system's own RNG if it was seeded
+    fn random(&self) -> f64 {
+        match &self.rng {
+            Some(rng) => rng.borrow_mut().r#gen::<f64>(),
+            None => rand::random::<f64>(),
+        }
+    }
+
+    // Most probable basis state and its probability, without collapsing the system
+    pub fn argmax_state(&self) -> (Vec<usize>, f64) {
+        let (state, probability) = self
             .values
             .iter()
+            .map(|n| n.abs_squared())
             .enumerate()
-            .filter(|(idx, _)| idx % modulo >= constraint)
-            .map(|(_, n)| n.abs_squared())
-            .sum();
+            .fold((0, 0.0), |best, (idx, p)| if p > best.1 { (idx, p) } else { best });
 
-        let rand_state = rand::random::<f64>();
-        let state = rand_state < probability_one;
+        (index_to_bits(state, self.len), probability)
+    }
 
-        let keep = (0..self.values.len())
-            .into_iter()
-            .map(|idx| idx % modulo >= constraint)
-            .map(|b| if state { b } else { !b })
-            .collect::<Vec<bool>>();
+    pub fn measure_single(&mut self, target: usize) -> usize {
+        self.measure_with_probability(target).0
+    }
 
-        let measured = self
-            .values
+    // Measures one qubit and flips it back to |0⟩ if it read 1, returning the outcome
+    // Unlike measure_single the qubit is always left in |0⟩, ready to be reused
+    pub fn measure_reset(&mut self, target: usize) -> usize {
+        let outcome = self.measure_single(target);
+        if outcome == 1 {
+            self.apply_single(target, &Matrix::pauli_x());
+        }
+        outcome
+    }
+
+    // Puts one qubit back to |0⟩ whatever state it was in, like a reset instruction
+    // The same as measure_reset without the outcome, the rest of the state is left collapsed
+    pub fn reset_qubit(&mut self, target: usize) {
+        self.measure_reset(target);
+    }
+
+    // Measures one qubit along the X, Y or Z axis, leaving it in the eigenstate it read
+    // The basis is rotated onto Z, measured there and rotated back again
+    pub fn measure_single_basis(&mut self, target: usize, basis: Basis) -> usize {
+        // Gates in the order they're applied
+        let (to_z, from_z) = match basis {
+            Basis::X => (vec![Matrix::hadamard()], vec![Matrix::hadamard()]),
+            Basis::Y => {
+                (vec![Matrix::sdg(), Matrix::hadamard()], vec![Matrix::hadamard(), Matrix::s()])
+            }
+            Basis::Z => return self.measure_single(target),
+        };
+
+        to_z.iter().for_each(|gate| self.apply_single(target, gate));
+        let outcome = self.measure_single(target);
+        from_z.iter().for_each(|gate| self.apply_single(target, gate));
+        outcome
+    }
+
+    // Measures one qubit, returning the outcome and how likely it was beforehand
+    pub fn measure_with_probability(&mut self, target: usize) -> (usize, f64) {
+        let rand_state = self.random();
+        let state = if rand_state < self.outcome_probability(target, 1) { 1 } else { 0 };
+
+        (state, self.postselect(target, state))
+    }
+
+    // Chance that measuring the target qubit gives outcome, without measuring it
+    pub fn outcome_probability(&self, target: usize, outcome: usize) -> f64 {
+        let bit = self.qubit_mask(target);
+
+        self.values
             .iter()
             .enumerate()
-            .map(|(idx, n)| if keep[idx] { *n } else { c!(0.0) })
-            .collect();
+            .filter(|(idx, _)| (idx & bit != 0) == (outcome == 1))
+            .map(|(_, n)| n.abs_squared())
+            .sum()
+    }
+
+    // Collapses the target qubit to outcome as if it had been measured that way
+    // Returns the probability of that outcome, if it's impossible the state is left alone
+    pub fn postselect(&mut self, target: usize, outcome: usize) -> f64 {
+        let probability = self.outcome_probability(target, outcome);
+        if probability == 0.0 {
+            return 0.0;
+        }
 
-        self.values = measured;
+        let bit = self.qubit_mask(target);
+        for (idx, value) in self.values.iter_mut().enumerate() {
+            if (idx & bit != 0) != (outcome == 1) {
+                *value = c!(0.0);
+            }
+        }
         self.renormalize();
 
-        if state { 1 } else { 0 }
+        probability
+    }
+
+    // Every possible outcome path when measuring the qubits in order, with their probabilities
+    // Works on copies of the state, so no randomness is involved and nothing is collapsed
+    pub fn measurement_tree(&self, order: &[usize]) -> MeasurementTree {
+        self.measurement_subtree(order, 1.0)
+    }
+
+    fn measurement_subtree(&self, order: &[usize], path_probability: f64) -> MeasurementTree {
+        let Some((target, rest)) = order.split_first() else {
+            return MeasurementTree { branches: Vec::new() };
+        };
+
+        let mut branches = Vec::new();
+        for outcome in 0..2 {
+            let mut branch = self.clone();
+            let probability = path_probability * branch.postselect(*target, outcome);
+            if probability > 1e-12 {
+                let subtree = branch.measurement_subtree(rest, probability);
+                branches.push((outcome, probability, subtree));
+            }
+        }
+
+        MeasurementTree { branches }
+    }
+
+    // Follows one path of measurement_tree, postselecting each qubit in order onto its
+    // outcome and returning the probability of the whole path
+    // The state is only changed if the path is possible
+    pub fn follow_path(&mut self, order: &[usize], outcomes: &[usize]) -> Result<f64, String> {
+        if order.len() != outcomes.len() {
+            return Err(format!("{} qubits but {} outcomes", order.len(), outcomes.len()));
+        }
+
+        let mut system = self.clone();
+        let mut path_probability = 1.0;
+        for (target, outcome) in order.iter().zip(outcomes) {
+            if *target >= self.len {
+                return Err(format!("qubit {target} out of range for {} qubits", self.len));
+            }
+            if *outcome > 1 {
+                return Err(format!("outcome {outcome} of qubit {target} isn't 0 or 1"));
+            }
+
+            path_probability *= system.postselect(*target, *outcome);
+            if path_probability <= 1e-12 {
+                return Err(format!("qubit {target} can't be measured as {outcome} on this path"));
+            }
+        }
+
+        *self = system;
+        Ok(path_probability)
+    }
+
+    // Measures each listed qubit in order, returning their outcomes in the same order
+    // Unlisted qubits stay in superposition, though they may be entangled with the measured ones
+    pub fn measure_subset(&mut self, targets: &[usize]) -> Vec<usize> {
+        targets.iter().map(|target| self.measure_single(*target)).collect()
+    }
+
+    // Measures the listed qubits as one register and reads them as a number,
+    // with the first listed qubit as the most significant bit
+    pub fn measure_register(&mut self, qubits: &[usize]) -> usize {
+        self.measure_subset(qubits).into_iter().fold(0, |value, bit| (value << 1) | bit)
     }
 
     pub fn renormalize(&mut self) {
@@ -257,32 +794,174 @@ impl QubitSystem {
         self.values = self.values.iter().map(|n| *n / c!(magnitude)).collect();
     }
 
+    // Marks the given basis states by flipping the sign of their amplitudes
+    // The core of Grover's search and Deutsch-Jozsa style oracles
+    pub fn phase_oracle(&mut self, marked: &[usize]) {
+        for &idx in marked {
+            self.values[idx] = self.values[idx] * c!(-1.0);
+        }
+    }
+
+    // Grover diffusion operator (2|s⟩⟨s| - I), reflects every amplitude about the mean
+    // Paired with phase_oracle this makes up one Grover iteration
+    pub fn grover_diffusion(&mut self) {
+        let mut mean = c!(0.0);
+        for value in &self.values {
+            mean += *value;
+        }
+        mean = mean / c!(self.values.len() as f64);
+
+        self.values = self.values.iter().map(|n| c!(2.0) * mean + *n * c!(-1.0)).collect();
+    }
+
     pub fn get_values(&self) -> Vec<ComplexNumber> {
         self.values.clone()
     }
 
+    // Every basis state as one bit per qubit alongside its amplitude, |0...0⟩ first
+    pub fn iter_amplitudes(&self) -> impl Iterator<Item = (Vec<usize>, ComplexNumber)> + '_ {
+        self.values.iter().enumerate().map(|(idx, value)| (index_to_bits(idx, self.len), *value))
+    }
+
     pub fn pretty_print(&self) -> String {
         format!("{:?}", self.values)
     }
 
     pub fn apply_gates(&mut self, gates: Vec<Gate>) {
-        let mut full_gate = matrix_new!([c!(1.0)]);
-
         for (idx, gate) in gates.iter().enumerate() {
-            if *gate == Gate::M {
-                self.measure_single(idx);
+            match gate {
+                Gate::M => {
+                    self.measure_single(idx);
+                }
+                Gate::MeasureReset => {
+                    self.measure_reset(idx);
+                }
+                _ => {}
             }
+        }
+
+        let full_gate = Self::column_operator(&gates);
+        self.apply_column_operator(&full_gate, &gates);
+    }
 
-            if let Gate::Other(_) = gate {
+    // The full operator of a column of gates, measurements count as the identity
+    // Runs of identities go into the kronecker product as one bigger identity
+    pub fn column_operator(gates: &[Gate]) -> Matrix {
+        let mut full_gate = matrix_new!([c!(1.0)]);
+        let mut identities = 0;
+
+        for gate in gates.iter().filter(|gate| !matches!(gate, Gate::Other(_))) {
+            if gate.is_identity() || matches!(gate, Gate::M | Gate::MeasureReset) {
+                identities += 1;
                 continue;
             }
+            if identities > 0 {
+                full_gate = full_gate.tensor(&Matrix::identity(1 << identities));
+                identities = 0;
+            }
+            full_gate = full_gate.tensor(&gate.cached_matrix());
+        }
 
-            full_gate = full_gate * gate.to_matrix();
+        if identities > 0 {
+            full_gate = full_gate.tensor(&Matrix::identity(1 << identities));
         }
+        full_gate
+    }
 
-        assert_eq!(self.values.len(), full_gate.len());
+    // Applies an operator built by column_operator, the gates are only needed for their
+    // global phases. Measurements have to be done beforehand, see apply_gates
+    pub fn apply_column_operator(&mut self, operator: &Matrix, gates: &[Gate]) {
+        assert_eq!(self.values.len(), operator.len());
 
-        self.values = full_gate.dot(&self.values);
+        for gate in gates.iter().filter(|gate| !matches!(gate, Gate::Other(_))) {
+            self.global_phase = self.global_phase * gate.global_phase();
+        }
+        self.values = operator.dot(&self.values);
+    }
+
+    // Fidelity |⟨ψ|φ⟩|² between two pure states of the same size
+    // 1 for identical states (up to global phase), 0 for orthogonal ones
+    pub fn fidelity(&self, other: &QubitSystem) -> f64 {
+        assert_eq!(self.values.len(), other.values.len(), "systems must be the same size");
+
+        let mut overlap = c!(0.0);
+        for (a, b) in self.values.iter().zip(&other.values) {
+            overlap += a.conjugate() * *b;
+        }
+
+        overlap.abs_squared()
+    }
+
+    // Expectation value ⟨ψ|O|ψ⟩ of a Hermitian observable over the whole system
+    pub fn expectation(&self, observable: &Matrix) -> f64 {
+        assert_eq!(observable.len(), self.values.len(), "observable must match the system size");
+
+        let applied = observable.dot(&self.values);
+        let mut total = c!(0.0);
+        for (a, b) in self.values.iter().zip(&applied) {
+            total += a.conjugate() * *b;
+        }
+
+        total.real
+    }
+
+    // Estimates ⟨O⟩ the way an experiment would, by measuring in the observable's eigenbasis
+    // shots times and averaging the eigenvalues seen. Returns the estimate and its standard
+    // error, which shrinks like 1/√shots. The state itself is left untouched
+    pub fn estimate_expectation(
+        &self,
+        observable: &Matrix,
+        shots: usize,
+        rng: &mut impl Rng,
+    ) -> (f64, f64) {
+        assert_eq!(observable.len(), self.values.len(), "observable must match the system size");
+        assert!(shots > 1, "need at least two shots to estimate an error");
+
+        // A Hermitian matrix A + iB acts like the real symmetric [[A, -B], [B, A]] on
+        // [Re ψ, Im ψ]. Every eigenvalue shows up twice there, and the squared overlaps
+        // with each pair add up to the complex eigenvector's measurement probability
+        let n = self.values.len();
+        let real: Vec<Vec<f64>> = (0..2 * n)
+            .map(|i| {
+                (0..2 * n)
+                    .map(|j| {
+                        let value = observable[i % n][j % n];
+                        match (i < n, j < n) {
+                            (true, true) | (false, false) => value.real,
+                            (true, false) => -value.imaginary,
+                            (false, true) => value.imaginary,
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        let state: Vec<f64> = self
+            .values
+            .iter()
+            .map(|v| v.real)
+            .chain(self.values.iter().map(|v| v.imaginary))
+            .collect();
+
+        let (eigenvalues, eigenvectors) = symmetric_eigen(real);
+        let probabilities: Vec<f64> = eigenvectors
+            .iter()
+            .map(|vector| vector.iter().zip(&state).map(|(a, b)| a * b).sum::<f64>().powi(2))
+            .collect();
+
+        let samples: Vec<f64> = (0..shots)
+            .map(|_| eigenvalues[select_state(&probabilities, rng.r#gen::<f64>())])
+            .collect();
+        let mean = samples.iter().sum::<f64>() / shots as f64;
+        let variance =
+            samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (shots - 1) as f64;
+
+        (mean, (variance / shots as f64).sqrt())
+    }
+
+    // Expectation value of a Pauli string like "XZI", one character per qubit
+    pub fn expectation_pauli(&self, s: &str) -> f64 {
+        assert_eq!(s.chars().count(), self.len, "pauli string needs one character per qubit");
+        self.expectation(&pauli_string_matrix(s))
     }
 
     pub fn density_matrix(&self) -> Matrix {
@@ -290,57 +969,265 @@ impl QubitSystem {
 
         for i in 0..self.values.len() {
             for j in 0..self.values.len() {
-                let bra = self.values[i].conjugate(); 
-                let ket = self.values[j]; 
+                // ρ = |ψ⟩⟨ψ|, so ρ_ij = ψ_i · conj(ψ_j)
+                let ket = self.values[i];
+                let bra = self.values[j].conjugate();
 
-                let outer_product = bra * ket;
+                let outer_product = ket * bra;
                 density_matrix[i][j] += outer_product;
             }
         }
 
         density_matrix
     }
-}
 
-pub fn partial_trace(density_matrix: Matrix, qubit_idx: usize, num_qubits: usize) -> Matrix {
-    let size = 2_usize.pow(num_qubits as u32); 
-    let reduced_size = size / 2;
+    // Purity Tr(ρ²) of the whole system, always 1 since a QubitSystem is a pure state
+    // Parts of it can be less pure, see qubit_purities
+    pub fn purity(&self) -> f64 {
+        purity(&self.density_matrix())
+    }
 
-    let mut reduced_density_matrix = Matrix::new(vec![vec![c!(0.0); reduced_size]; reduced_size]);
+    // Bloch vector [x, y, z] of every qubit in the system
+    // Each qubit's reduced density matrix is found by tracing out all the others
+    pub fn bloch_vectors(&self) -> Vec<Vec<f64>> {
+        let density = self.density_matrix();
+
+        (0..self.len)
+            .map(|qubit| {
+                let others: Vec<usize> = (0..self.len).filter(|i| *i != qubit).collect();
+                bloch_vector(partial_trace_out(&density, &others, self.len))
+            })
+            .collect()
+    }
 
-    for i in 0..size {
-        for j in 0..size {
-            let row_idx = (i >> qubit_idx) & 1; 
-            let col_idx = (j >> qubit_idx) & 1; 
+    // Textbook [x, y, z] Bloch vector of one qubit, found by tracing out all the others
+    pub fn bloch_vector_for(&self, qubit: usize) -> [f64; 3] {
+        let others: Vec<usize> = (0..self.len).filter(|i| *i != qubit).collect();
+        let vector = bloch_vector(partial_trace_out(&self.density_matrix(), &others, self.len));
+        [vector[0], vector[1], vector[2]]
+    }
 
-            if row_idx == col_idx {
-                let reduced_i = (i & ((1 << qubit_idx) - 1)) | ((i >> (qubit_idx + 1)) << qubit_idx);
-                let reduced_j = (j & ((1 << qubit_idx) - 1)) | ((j >> (qubit_idx + 1)) << qubit_idx);
+    // Purity of every qubit's reduced state, 1 when it's unentangled down to 0.5
+    // when it's maximally entangled with the rest of the system
+    pub fn qubit_purities(&self) -> Vec<f64> {
+        let density = self.density_matrix();
+
+        (0..self.len)
+            .map(|qubit| {
+                let others: Vec<usize> = (0..self.len).filter(|i| *i != qubit).collect();
+                purity(&partial_trace_out(&density, &others, self.len))
+            })
+            .collect()
+    }
+}
 
-                reduced_density_matrix[reduced_i][reduced_j] = reduced_density_matrix[reduced_i][reduced_j] + density_matrix[i][j];
-            }
+// Purity Tr(ρ²) of a density matrix, 1 for a pure state and 1/d for a maximally mixed one
+pub fn purity(density: &Matrix) -> f64 {
+    let mut total = 0.0;
+    for i in 0..density.len() {
+        for j in 0..density.len() {
+            // ρ is Hermitian, so ρ[i][j]·ρ[j][i] = |ρ[i][j]|²
+            total += density[i][j].abs_squared();
         }
     }
 
-    reduced_density_matrix
+    total
 }
 
-pub fn bloch_vector(density_matrix: Matrix) -> Vec<f64> {
-    let rho_00 = density_matrix[0][0].real;
-    let rho_11 = density_matrix[1][1].real;
-    let c = density_matrix[0][1];
-
-    let r_x = 2.0 * c.real;
-    let r_y = 2.0 * c.imaginary;
-    let r_z = rho_00 - rho_11;
+// Basis states where two systems' amplitudes differ by more than epsilon (in either part),
+// as (bits, amplitude in a, amplitude in b). Amplitudes are compared as they are, so
+// states that only differ by a global phase still show up
+pub fn diff_states(
+    a: &QubitSystem,
+    b: &QubitSystem,
+    epsilon: f64,
+) -> Vec<(Vec<usize>, ComplexNumber, ComplexNumber)> {
+    assert_eq!(a.values.len(), b.values.len(), "systems must be the same size");
+
+    a.iter_amplitudes()
+        .zip(b.values.iter())
+        .filter(|((_, left), right)| !left.approx_eq(right, epsilon))
+        .map(|((bits, left), right)| (bits, left, *right))
+        .collect()
+}
 
-    vec![r_x, r_y, r_z]
+// Outcome paths of a sequence of measurements, see QubitSystem::measurement_tree
+#[derive(Debug, PartialEq)]
+pub struct MeasurementTree {
+    // Each possible outcome of the next measurement, the probability of the whole path
+    // up to and including it, and the measurements that follow
+    pub branches: Vec<(usize, f64, MeasurementTree)>,
 }
 
+// Turns sampled counts back into an approximate probability distribution
+pub fn counts_to_probabilities(
+    counts: &HashMap<Vec<usize>, usize>,
+) -> HashMap<Vec<usize>, f64> {
+    let shots = counts.values().sum::<usize>() as f64;
+
+    counts.iter().map(|(state, count)| (state.clone(), *count as f64 / shots)).collect()
+}
+
+// Splits a basis state index into one bit per qubit, qubit 0 first
+fn index_to_bits(idx: usize, len: usize) -> Vec<usize> {
+    (0..len).rev().map(|i| (idx >> i) & 1).collect()
+}
+
+// Number of Grover iterations that maximises the chance of measuring a marked state
+// round((π/4)·sqrt(N/M)) for N basis states and M marked states
+pub fn optimal_grover_iterations(num_qubits: usize, num_marked: usize) -> usize {
+    let states = 2_usize.pow(num_qubits as u32) as f64;
+
+    (PI / 4.0 * (states / num_marked as f64).sqrt()).round() as usize
+}
+
+// Picks the basis state whose cumulative probability bucket contains rand_state
+// If float error leaves the total slightly under 1.0 and rand_state lands past it,
+// falls back to the last state with a nonzero probability rather than state 0
+fn select_state(probabilities: &[f64], rand_state: f64) -> usize {
+    let mut weight = 0.0;
+    for (idx, probability) in probabilities.iter().enumerate() {
+        weight += probability;
+        if rand_state <= weight {
+            return idx;
+        }
+    }
+
+    probabilities.iter().rposition(|p| *p > 0.0).unwrap_or(0)
+}
+
+// Traces a single qubit out of the density matrix
+// Unlike the rest of the crate qubit_idx counts from the least significant bit,
+// partial_trace_out takes qubits in the usual order
+pub fn partial_trace(density_matrix: Matrix, qubit_idx: usize, num_qubits: usize) -> Matrix {
+    let size = 2_usize.pow(num_qubits as u32); 
+    let reduced_size = size / 2;
+
+    let mut reduced_density_matrix = Matrix::new(vec![vec![c!(0.0); reduced_size]; reduced_size]);
+
+    for i in 0..size {
+        for j in 0..size {
+            let row_idx = (i >> qubit_idx) & 1; 
+            let col_idx = (j >> qubit_idx) & 1; 
+
+            if row_idx == col_idx {
+                let reduced_i = (i & ((1 << qubit_idx) - 1)) | ((i >> (qubit_idx + 1)) << qubit_idx);
+                let reduced_j = (j & ((1 << qubit_idx) - 1)) | ((j >> (qubit_idx + 1)) << qubit_idx);
+
+                reduced_density_matrix[reduced_i][reduced_j] = reduced_density_matrix[reduced_i][reduced_j] + density_matrix[i][j];
+            }
+        }
+    }
+
+    reduced_density_matrix
+}
+
+// Traces every listed qubit out of the density matrix in one pass
+// Qubits are numbered like everywhere else on QubitSystem, qubit 0 being the most significant
+pub fn partial_trace_out(density: &Matrix, remove: &[usize], num_qubits: usize) -> Matrix {
+    assert_eq!(density.len(), 1 << num_qubits, "density matrix must be 2^n by 2^n for n qubits");
+    for (idx, qubit) in remove.iter().enumerate() {
+        assert!(*qubit < num_qubits, "qubit {qubit} out of range for {num_qubits} qubits");
+        assert!(!remove[..idx].contains(qubit), "qubit {qubit} listed twice");
+    }
+
+    let bit = |qubit: usize| 1 << (num_qubits - 1 - qubit);
+    let removed_mask = remove.iter().fold(0, |acc, q| acc | bit(*q));
+    let keep: Vec<usize> = (0..num_qubits).filter(|q| !remove.contains(q)).map(bit).collect();
+
+    // Index of a full basis state within the reduced system
+    let reduce = |idx: usize| keep.iter().fold(0, |acc, b| (acc << 1) | (idx & b != 0) as usize);
+
+    let reduced_size = 1 << keep.len();
+    let mut reduced = Matrix::new(vec![vec![c!(0.0); reduced_size]; reduced_size]);
+
+    for i in 0..density.len() {
+        for j in 0..density.len() {
+            if i & removed_mask == j & removed_mask {
+                reduced[reduce(i)][reduce(j)] += density[i][j];
+            }
+        }
+    }
+
+    reduced
+}
+
+// The density matrix has to be a single qubit's, e.g. after partial_trace_out
+pub fn bloch_vector(density_matrix: Matrix) -> Vec<f64> {
+    assert_eq!(density_matrix.len(), 2, "bloch_vector needs a 2x2 density matrix");
+
+    let rho_00 = density_matrix[0][0].real;
+    let rho_11 = density_matrix[1][1].real;
+    let c = density_matrix[0][1];
+
+    // ρ_01 = (r_x - i·r_y) / 2
+    let r_x = 2.0 * c.real;
+    let r_y = -2.0 * c.imaginary;
+    let r_z = rho_00 - rho_11;
+
+    vec![r_x, r_y, r_z]
+}
+
+// bloch_vector, but giving an error when the vector ends up outside the unit sphere
+// (beyond epsilon), which only happens when the density matrix isn't a valid one
+pub fn bloch_vector_checked(
+    density_matrix: Matrix,
+    epsilon: f64,
+) -> Result<Vec<f64>, QuantumError> {
+    if density_matrix.len() != 2 {
+        return Err(QuantumError::DimensionMismatch { expected: 2, found: density_matrix.len() });
+    }
+
+    let vector = bloch_vector(density_matrix);
+    let length_squared: f64 = vector.iter().map(|n| n * n).sum();
+    if length_squared > 1.0 + epsilon {
+        return Err(QuantumError::OutsideBlochSphere(length_squared.sqrt()));
+    }
+    Ok(vector)
+}
+
+// Basis states whose amplitudes the gate would change when applied to targets (most
+// significant first) of a num_qubits system, e.g. a CNOT only touches the states where its
+// control is 1. Measurements can change every amplitude, and placeholders none of them
+pub fn affected_basis_states(gate: &Gate, targets: &[usize], num_qubits: usize) -> Vec<usize> {
+    if matches!(gate, Gate::M | Gate::MeasureReset) {
+        return (0..1 << num_qubits).collect();
+    }
+    let matrix = gate.to_matrix();
+    if matrix.len() != 1 << targets.len() {
+        return Vec::new();
+    }
+
+    // Rows of the gate that aren't the identity's, indexed by the targets' bits
+    let changed: Vec<bool> = (0..matrix.len())
+        .map(|row| {
+            (0..matrix.len()).any(|col| {
+                let identity = c!(if row == col { 1.0 } else { 0.0 });
+                !matrix[row][col].approx_eq(&identity, 1e-12)
+            })
+        })
+        .collect();
+
+    (0..1 << num_qubits)
+        .filter(|state| {
+            let row = targets
+                .iter()
+                .fold(0, |row, target| (row << 1) | ((state >> (num_qubits - 1 - target)) & 1));
+            changed[row]
+        })
+        .collect()
+}
+
+// Both tensors must have at least one value, or the result would silently be empty
 pub fn tensor_product(
     tensor1: Vec<ComplexNumber>,
     tensor2: Vec<ComplexNumber>,
 ) -> Vec<ComplexNumber> {
+    assert!(
+        !tensor1.is_empty() && !tensor2.is_empty(),
+        "tensor_product of an empty tensor, both sides need at least one value"
+    );
+
     let mut result = Vec::new();
 
     for x in &tensor1 {
@@ -352,7 +1239,8 @@ pub fn tensor_product(
     result
 }
 
-#[derive(Clone, PartialEq)]
+// Serialized with serde's default enum layout, e.g. "H" or {"RX": 0.5}
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub enum Gate {
     I,
     X,
@@ -360,21 +1248,120 @@ pub enum Gate {
     Z,
     H,
     M,
+    // Measures a qubit then puts it back to |0⟩, leaving the rest of the state collapsed
+    MeasureReset,
     P(f64),
     S,
+    Sdg,
+    T,
+    Tdg,
     RX(f64),
     RY(f64),
     RZ(f64),
+    // General single qubit unitary U(θ, φ, λ)
+    U3(f64, f64, f64),
+    // Rotation by an angle about an axis of the Bloch sphere, see Matrix::rotation
+    Rot([f64; 3], f64),
     CNOT,
+    ACNOT,
     CZ,
+    // Controlled phase, P(θ) on the second qubit when the first is |1⟩
+    CP(f64),
     SWAP,
     CCX,
     CCCX,
     CSWAP,
+    CCZ,
+    // The inner gate on the qubits after the first, applied only when the first is |1⟩
+    Controlled(Box<Gate>),
+    // Multiplies each basis state of the qubits it spans by e^(iθ), one angle per state
+    DiagonalPhase(Vec<f64>),
+    // Marks where a fresh qubit is set to |0⟩ (false) or |1⟩ (true)
+    // Acts as I or X, so it only initializes a qubit that's still |0⟩
+    Init(bool),
+    // Checks the whole system is in the given basis state, e.g. "01", without touching it
+    Assert(String),
     Other(String),
 }
 
+// A gate's variant (and those of any gates it wraps) and the bits of its parameters
+type MatrixKey = (Vec<Discriminant<Gate>>, Vec<u64>);
+
+thread_local! {
+    static MATRIX_CACHE: RefCell<HashMap<MatrixKey, Rc<Matrix>>> = RefCell::new(HashMap::new());
+}
+
+// Stops parameter sweeps from growing the cache forever
+const MATRIX_CACHE_LIMIT: usize = 256;
+
 impl Gate {
+    // One of every built-in gate with zeroed parameters, in palette order
+    // Custom gates (Other) aren't built in, so they're left out
+    pub fn all_builtin() -> Vec<Gate> {
+        vec![
+            Gate::I,
+            Gate::X,
+            Gate::Y,
+            Gate::Z,
+            Gate::H,
+            Gate::M,
+            Gate::MeasureReset,
+            Gate::S,
+            Gate::Sdg,
+            Gate::T,
+            Gate::Tdg,
+            Gate::P(0.0),
+            Gate::RX(0.0),
+            Gate::RY(0.0),
+            Gate::RZ(0.0),
+            Gate::U3(0.0, 0.0, 0.0),
+            Gate::Rot([0.0, 0.0, 1.0], 0.0),
+            Gate::CNOT,
+            Gate::ACNOT,
+            Gate::CZ,
+            Gate::CP(0.0),
+            Gate::SWAP,
+            Gate::CCX,
+            Gate::CCCX,
+            Gate::CSWAP,
+            Gate::CCZ,
+            Gate::Controlled(Box::new(Gate::H)),
+            Gate::DiagonalPhase(vec![0.0, 0.0]),
+            Gate::Init(false),
+            Gate::Assert(String::from("0")),
+        ]
+    }
+
+    // Shared, memoized matrix for the gate, used when stepping so replaying a circuit
+    // doesn't rebuild (and redo the trig for) every gate in every column
+    // Editing an angle needs no invalidation, the new angle is just a new key
+    pub fn cached_matrix(&self) -> Rc<Matrix> {
+        let key = self.matrix_key();
+
+        MATRIX_CACHE.with_borrow_mut(|cache| {
+            if cache.len() >= MATRIX_CACHE_LIMIT {
+                cache.clear();
+            }
+            cache.entry(key).or_insert_with(|| Rc::new(self.to_matrix())).clone()
+        })
+    }
+
+    fn matrix_key(&self) -> MatrixKey {
+        match self {
+            Gate::Controlled(inner) => {
+                let (mut variants, params) = inner.matrix_key();
+                variants.insert(0, discriminant(self));
+                (variants, params)
+            }
+            // Init's value isn't an editable parameter, but it still picks the matrix
+            Gate::Init(one) => (vec![discriminant(self)], vec![*one as u64]),
+            _ => {
+                let params = self.params().iter().map(|param| param.to_bits()).collect();
+                (vec![discriminant(self)], params)
+            }
+        }
+    }
+
     pub fn to_matrix(&self) -> Matrix {
         match self {
             Gate::I => Matrix::identity2(),
@@ -382,26 +1369,149 @@ impl Gate {
             Gate::Y => Matrix::pauli_y(),
             Gate::Z => Matrix::pauli_z(),
             Gate::H => Matrix::hadamard(),
-            Gate::M => Matrix::identity2(),
+            Gate::M | Gate::MeasureReset => Matrix::identity2(),
             Gate::S => Matrix::s(),
+            Gate::Sdg => Matrix::sdg(),
+            Gate::T => Matrix::t(),
+            Gate::Tdg => Matrix::tdg(),
             Gate::P(theta) => Matrix::phase(*theta),
             Gate::RX(theta) => Matrix::rx(*theta),
             Gate::RY(theta) => Matrix::ry(*theta),
             Gate::RZ(theta) => Matrix::rz(*theta),
+            Gate::U3(theta, phi, lambda) => Matrix::u3(*theta, *phi, *lambda),
+            Gate::Rot(axis, theta) => Matrix::rotation(*axis, *theta),
             Gate::CNOT => Matrix::cnot(),
+            Gate::ACNOT => Matrix::acnot(),
             Gate::CZ => Matrix::cz(),
+            Gate::CP(theta) => Matrix::cphase(*theta),
             Gate::SWAP => Matrix::swap(),
             Gate::CCX => Matrix::ccx(),
             Gate::CCCX => Matrix::cccx(),
             Gate::CSWAP => Matrix::cswap(),
+            Gate::CCZ => Matrix::ccz(),
+            Gate::Controlled(inner) => Matrix::controlled_on(&inner.to_matrix(), &[true]),
+            Gate::DiagonalPhase(phases) => Matrix::diagonal_phase(phases),
+            Gate::Init(false) => Matrix::identity2(),
+            Gate::Init(true) => Matrix::pauli_x(),
+            Gate::Assert(_) => Matrix::identity2(),
             Gate::Other(_) => matrix_new!([c!(1.0)]),
         }
     }
 
+    // Number of qubits the gate acts on, without building its matrix
+    // Placeholder gates (Other) take up no qubits of their own
+    pub fn num_qubits(&self) -> usize {
+        match self {
+            Gate::CNOT | Gate::ACNOT | Gate::CZ | Gate::CP(_) | Gate::SWAP => 2,
+            Gate::CCX | Gate::CSWAP | Gate::CCZ => 3,
+            Gate::CCCX => 4,
+            Gate::Controlled(inner) => inner.num_qubits().max(1) + 1,
+            Gate::DiagonalPhase(phases) => phases.len().trailing_zeros() as usize,
+            Gate::Other(_) => 0,
+            _ => 1,
+        }
+    }
+
+    // Whether the gate leaves every state as it is: identities, asserts, Init(false)
+    // and the continuation cells of bigger gates. Measurements don't count
+    pub fn is_identity(&self) -> bool {
+        match self {
+            Gate::I | Gate::Assert(_) | Gate::Init(false) => true,
+            Gate::Other(name) => name == "none",
+            _ => false,
+        }
+    }
+
+    // Global phase factor the gate carries on top of a relative phase
+    // This crate's RZ(θ) is e^(iθ/2)·P(-θ) and a Rot about +z is e^(-iθ/2)·P(θ), a Rot
+    // about -z turns the other way. Every other gate is tracked as having none
+    pub fn global_phase(&self) -> ComplexNumber {
+        match self {
+            Gate::RZ(theta) => c!(0.0, theta / 2.0).exp(),
+            Gate::Rot([x, y, z], theta) if *x == 0.0 && *y == 0.0 && *z != 0.0 => {
+                c!(0.0, -theta * z.signum() / 2.0).exp()
+            }
+            _ => c!(1.0),
+        }
+    }
+
+    // CSS class for the gate's cell in the editor, e.g. "gateCNOT"
+    // Custom gate names are cut down to their alphanumeric words joined in PascalCase,
+    // so "my gate!" becomes "gateMyGate" rather than something that isn't a valid class
+    // Controlled gates are sized by how many qubits they span, e.g. "gateControlled2"
+    pub fn css_class(&self) -> String {
+        if let Gate::Controlled(_) = self {
+            return format!("gateControlled{}", self.num_qubits());
+        }
+        let Gate::Other(name) = self else {
+            return format!("gate{self:?}");
+        };
+
+        let words: String = name
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(|word| word[..1].to_ascii_uppercase() + &word[1..])
+            .collect();
+
+        if words.is_empty() { String::from("gateCustom") } else { format!("gate{words}") }
+    }
+
+    // The same gate with its angle replaced, gates without an angle are returned as they are
+    pub fn with_angle(&self, value: f64) -> Gate {
+        match self {
+            Gate::P(_) => Gate::P(value),
+            Gate::RX(_) => Gate::RX(value),
+            Gate::RY(_) => Gate::RY(value),
+            Gate::RZ(_) => Gate::RZ(value),
+            Gate::CP(_) => Gate::CP(value),
+            Gate::Controlled(inner) => Gate::Controlled(Box::new(inner.with_angle(value))),
+            other => other.clone(),
+        }
+    }
+
+    // Whether both gates are the same variant, ignoring angles and custom gate names
+    pub fn same_kind(&self, other: &Gate) -> bool {
+        match (self, other) {
+            (Gate::Controlled(a), Gate::Controlled(b)) => a.same_kind(b),
+            _ => discriminant(self) == discriminant(other),
+        }
+    }
+
+    // Whether the gate takes a parameter the editor lets the user change
     pub fn is_variable(&self) -> bool {
+        !self.params().is_empty()
+    }
+
+    // The gate's parameters in order, empty for gates without any
+    pub fn params(&self) -> Vec<f64> {
+        match self {
+            Gate::P(theta)
+            | Gate::RX(theta)
+            | Gate::RY(theta)
+            | Gate::RZ(theta)
+            | Gate::CP(theta) => vec![*theta],
+            Gate::U3(theta, phi, lambda) => vec![*theta, *phi, *lambda],
+            Gate::Rot([x, y, z], theta) => vec![*x, *y, *z, *theta],
+            Gate::DiagonalPhase(phases) => phases.clone(),
+            Gate::Controlled(inner) => inner.params(),
+            _ => Vec::new(),
+        }
+    }
+
+    // The same gate with one parameter replaced, an index past the end changes nothing
+    pub fn with_param(&self, index: usize, value: f64) -> Gate {
+        let mut params = self.params();
+        if index >= params.len() {
+            return self.clone();
+        }
+        params[index] = value;
+
         match self {
-            Gate::P(_) | Gate::RX(_) | Gate::RY(_) | Gate::RZ(_) => true,
-            _ => false
+            Gate::U3(..) => Gate::U3(params[0], params[1], params[2]),
+            Gate::Rot(..) => Gate::Rot([params[0], params[1], params[2]], params[3]),
+            Gate::DiagonalPhase(_) => Gate::DiagonalPhase(params),
+            Gate::Controlled(inner) => Gate::Controlled(Box::new(inner.with_param(index, value))),
+            other => other.with_angle(value),
         }
     }
 }
@@ -415,17 +1525,30 @@ impl Debug for Gate {
             Self::Z => write!(f, "Z"),
             Self::H => write!(f, "H"),
             Self::M => write!(f, "M"),
+            Self::MeasureReset => write!(f, "MR"),
             Self::P(_) => write!(f, "P"),
             Self::RX(_) => write!(f, "RX"),
             Self::RY(_) => write!(f, "RY"),
             Self::RZ(_) => write!(f, "RZ"),
+            Self::U3(..) => write!(f, "U3"),
+            Self::Rot(..) => write!(f, "Rot"),
             Self::S => write!(f, "S"),
+            Self::Sdg => write!(f, "Sdg"),
+            Self::T => write!(f, "T"),
+            Self::Tdg => write!(f, "Tdg"),
             Gate::CNOT => write!(f, "CNOT"),
+            Gate::ACNOT => write!(f, "ACNOT"),
             Gate::CZ => write!(f, "CZ"),
+            Gate::CP(_) => write!(f, "CP"),
             Gate::SWAP => write!(f, "SWAP"),
             Gate::CCX => write!(f, "CCX"),
             Gate::CCCX => write!(f, "CCCX"),
             Gate::CSWAP => write!(f, "CSWAP"),
+            Gate::CCZ => write!(f, "CCZ"),
+            Gate::Controlled(inner) => write!(f, "C{inner:?}"),
+            Gate::DiagonalPhase(_) => write!(f, "Diag"),
+            Gate::Init(_) => write!(f, "Init"),
+            Gate::Assert(_) => write!(f, "Assert"),
             Gate::Other(name) => write!(f, "{name}"),
         }
     }
@@ -433,6 +1556,7 @@ impl Debug for Gate {
 
 #[cfg(test)]
 mod tests {
+    use rand::{SeedableRng, rngs::StdRng};
     use std::f64::consts::{SQRT_2, SQRT_3};
 
     use super::*;
@@ -500,6 +1624,16 @@ mod tests {
         assert_eq!(Qubit::one().phase(PI), Qubit::one().pauli_z());
     }
 
+    #[test]
+    pub fn phase_inverses() {
+        let plus = Qubit::zero().hadamard();
+        let t = Gate::T.to_matrix();
+
+        assert_eq!(plus.dot_matrix(Matrix::s()).dot_matrix(Matrix::sdg()), plus);
+        assert_eq!(plus.dot_matrix(Matrix::t()).dot_matrix(Matrix::tdg()), plus);
+        assert_eq!(plus.dot_matrix(t.clone()).dot_matrix(t), plus.dot_matrix(Matrix::s()));
+    }
+
     #[test]
     pub fn system_single_gate() {
         let mut system = QubitSystem::new(vec![Qubit::zero(), Qubit::one(), Qubit::zero()]);
@@ -549,6 +1683,14 @@ mod tests {
         assert_eq!(system.measure(), vec![1, 0]);
     }
 
+    #[test]
+    pub fn register_value() {
+        let mut system = QubitSystem::new(vec![Qubit::one(), Qubit::zero(), Qubit::one()]);
+        assert_eq!(system.measure_register(&[0, 1, 2]), 5);
+        assert_eq!(system.measure_register(&[2, 1, 0]), 5);
+        assert_eq!(system.measure_register(&[1, 0]), 1);
+    }
+
     #[test]
     pub fn renormalize() {
         let mut system = QubitSystem::from_tensor(vec![c!(SQRT_THIRD), c!(0.0), c!(0.0), c!(SQRT_THIRD), c!(0.0), c!(0.0), c!(SQRT_THIRD)], 3);
@@ -557,6 +1699,1001 @@ mod tests {
         assert!(system.system_normal());
     }
 
+    #[test]
+    pub fn normalized_construction() {
+        let qubit = Qubit::new(c!(0.6002), c!(0.8001));
+        let system = QubitSystem::new_normalized(vec![qubit; 6]);
+        let norm = system.get_values().iter().map(|n| n.abs_squared()).sum::<f64>();
+
+        assert!(system.system_normal());
+        assert!((norm - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    pub fn seeded_measurements() {
+        let run = |seed| {
+            let mut system = QubitSystem::new_seeded(vec![Qubit::zero().hadamard(); 8], seed);
+            let counts = system.sample(50);
+            let first = system.measure_single(3);
+            (counts, first, system.measure())
+        };
+
+        assert_eq!(run(42), run(42));
+        assert_ne!(run(42), run(43));
+    }
+
+    #[test]
+    pub fn measure_boundary() {
+        let probabilities = vec![0.25, 0.25, 0.25, 0.2499999, 0.0];
+
+        assert_eq!(select_state(&probabilities, 0.1), 0);
+        assert_eq!(select_state(&probabilities, 0.99999995), 3);
+        assert_eq!(select_state(&probabilities, 1.0), 3);
+    }
+
+    #[test]
+    pub fn phase_oracle() {
+        let mut system = QubitSystem::new(vec![Qubit::zero().hadamard(); 3]);
+        let amplitude = c!(1.0 / 8.0_f64.sqrt());
+        system.phase_oracle(&[5]);
+
+        for (idx, value) in system.get_values().into_iter().enumerate() {
+            if idx == 5 {
+                assert_eq!(value, amplitude * c!(-1.0));
+            } else {
+                assert_eq!(value, amplitude);
+            }
+        }
+    }
+
+    #[test]
+    pub fn variable_gates() {
+        let variable = [
+            Gate::P(0.5),
+            Gate::RX(0.5),
+            Gate::RY(0.5),
+            Gate::RZ(0.5),
+            Gate::U3(0.5, 0.5, 0.5),
+            Gate::Rot([1.0, 0.0, 0.0], 0.5),
+            Gate::CP(0.5),
+            Gate::DiagonalPhase(vec![0.5, 0.5]),
+        ];
+        for gate in variable {
+            assert!(gate.is_variable(), "{gate:?} should be variable");
+        }
+
+        let fixed = [
+            Gate::I,
+            Gate::X,
+            Gate::Y,
+            Gate::Z,
+            Gate::H,
+            Gate::M,
+            Gate::MeasureReset,
+            Gate::S,
+            Gate::Sdg,
+            Gate::T,
+            Gate::Tdg,
+            Gate::CNOT,
+            Gate::ACNOT,
+            Gate::CZ,
+            Gate::SWAP,
+            Gate::CCX,
+            Gate::CCCX,
+            Gate::CSWAP,
+            Gate::CCZ,
+            Gate::Init(true),
+            Gate::Assert(String::from("0")),
+            Gate::Other(String::from("none")),
+        ];
+        for gate in fixed {
+            assert!(!gate.is_variable(), "{gate:?} should not be variable");
+        }
+    }
+
+    #[test]
+    pub fn initialization() {
+        let mut system = QubitSystem::new(vec![Qubit::zero(), Qubit::zero()]);
+        system.apply(&Gate::Init(true), &[0]);
+        system.apply(&Gate::Init(false), &[1]);
+
+        assert!(system.matches_ket("10"));
+    }
+
+    #[test]
+    pub fn ccz_phase() {
+        let values: Vec<ComplexNumber> = (1..=8).map(|n| c!(n as f64 / 204f64.sqrt())).collect();
+        let mut system = QubitSystem::from_tensor(values.clone(), 3);
+        system.apply(&Gate::CCZ, &[0, 1, 2]);
+
+        let expected: Vec<ComplexNumber> = values
+            .iter()
+            .enumerate()
+            .map(|(i, v)| if i == 7 { *v * c!(-1.0) } else { *v })
+            .collect();
+        assert_eq!(system.get_values(), expected);
+    }
+
+    #[test]
+    pub fn gate_kinds() {
+        assert!(Gate::RX(0.5).same_kind(&Gate::RX(0.3)));
+        assert!(!Gate::RX(0.5).same_kind(&Gate::RY(0.5)));
+        assert!(Gate::CNOT.same_kind(&Gate::CNOT));
+        assert!(Gate::Other(String::from("a")).same_kind(&Gate::Other(String::from("b"))));
+        assert!(Gate::RX(0.5) != Gate::RX(0.3));
+    }
+
+    #[test]
+    pub fn css_classes() {
+        assert_eq!(Gate::CNOT.css_class(), "gateCNOT");
+        assert_eq!(Gate::RX(0.5).css_class(), "gateRX");
+        assert_eq!(Gate::Other(String::from("my gate!")).css_class(), "gateMyGate");
+        assert_eq!(Gate::Other(String::from("none")).css_class(), "gateNone");
+        assert_eq!(Gate::Other(String::from("<>")).css_class(), "gateCustom");
+        assert_eq!(Gate::Controlled(Box::new(Gate::CZ)).css_class(), "gateControlled3");
+    }
+
+    #[test]
+    pub fn gate_sizes() {
+        let sizes = [
+            (Gate::I, 1),
+            (Gate::X, 1),
+            (Gate::Y, 1),
+            (Gate::Z, 1),
+            (Gate::H, 1),
+            (Gate::M, 1),
+            (Gate::MeasureReset, 1),
+            (Gate::P(0.5), 1),
+            (Gate::S, 1),
+            (Gate::Sdg, 1),
+            (Gate::T, 1),
+            (Gate::Tdg, 1),
+            (Gate::RX(0.5), 1),
+            (Gate::RY(0.5), 1),
+            (Gate::RZ(0.5), 1),
+            (Gate::U3(0.5, 0.5, 0.5), 1),
+            (Gate::Rot([1.0, 0.0, 0.0], 0.5), 1),
+            (Gate::CNOT, 2),
+            (Gate::ACNOT, 2),
+            (Gate::CZ, 2),
+            (Gate::CP(0.5), 2),
+            (Gate::SWAP, 2),
+            (Gate::CCX, 3),
+            (Gate::CCCX, 4),
+            (Gate::CSWAP, 3),
+            (Gate::CCZ, 3),
+            (Gate::Controlled(Box::new(Gate::H)), 2),
+            (Gate::Controlled(Box::new(Gate::CZ)), 3),
+            (Gate::DiagonalPhase(vec![0.0; 8]), 3),
+            (Gate::Init(true), 1),
+            (Gate::Assert(String::from("00")), 1),
+            (Gate::Other(String::from("none")), 0),
+        ];
+
+        for (gate, size) in sizes {
+            assert_eq!(gate.num_qubits(), size, "{gate:?}");
+            assert_eq!(gate.to_matrix().len(), 1 << size, "{gate:?}");
+        }
+    }
+
+    #[test]
+    pub fn grover_search() {
+        assert_eq!(optimal_grover_iterations(3, 1), 2);
+
+        let mut system = QubitSystem::new(vec![Qubit::zero().hadamard(); 3]);
+        for _ in 0..optimal_grover_iterations(3, 1) {
+            system.phase_oracle(&[6]);
+            system.grover_diffusion();
+        }
+
+        assert!(system.get_values()[6].abs_squared() > 0.9);
+    }
+
+    #[test]
+    pub fn argmax() {
+        let mut system = QubitSystem::new(vec![Qubit::zero().hadamard(); 3]);
+        for _ in 0..optimal_grover_iterations(3, 1) {
+            system.phase_oracle(&[5]);
+            system.grover_diffusion();
+        }
+        let values = system.get_values();
+
+        let (state, probability) = system.argmax_state();
+        assert_eq!(state, vec![1, 0, 1]);
+        assert!(probability > 0.9);
+        assert_eq!(system.get_values(), values);
+    }
+
+    #[test]
+    pub fn conditional_gate() {
+        let mut system = QubitSystem::new(vec![Qubit::zero(), Qubit::one()]);
+        system.apply_if(false, 0, Matrix::pauli_x());
+        assert_eq!(system.measure(), vec![0, 1]);
+
+        system.apply_if(true, 0, Matrix::pauli_x());
+        assert_eq!(system.measure(), vec![1, 1]);
+    }
+
+    #[test]
+    pub fn fast_column() {
+        let none = || Gate::Other(String::from("none"));
+        let columns = vec![
+            vec![Gate::RX(0.3), Gate::CNOT, none(), Gate::H],
+            vec![Gate::CCX, none(), none(), Gate::Y],
+            vec![Gate::SWAP, none(), Gate::S, Gate::P(1.2)],
+            vec![Gate::X, Gate::CSWAP, none(), none()],
+            vec![Gate::CCCX, none(), none(), none()],
+        ];
+
+        let mut dense = QubitSystem::new(vec![Qubit::zero().hadamard(); 4]);
+        let mut fast = QubitSystem::new(vec![Qubit::zero().hadamard(); 4]);
+        dense.apply_gate(0, Matrix::phase(0.7));
+        fast.apply_single(0, &Matrix::phase(0.7));
+
+        for column in columns {
+            dense.apply_gates(column.clone());
+            fast.apply_column_fast(&column);
+            assert_eq!(dense.get_values(), fast.get_values());
+        }
+    }
+
+    #[test]
+    pub fn non_adjacent_gate() {
+        let mut system = QubitSystem::new(vec![Qubit::one(), Qubit::zero(), Qubit::zero()]);
+        system.apply_to(&[0, 2], &Matrix::cnot());
+        assert_eq!(system.measure(), vec![1, 0, 1]);
+
+        let mut system = QubitSystem::new(vec![Qubit::zero(), Qubit::zero(), Qubit::one()]);
+        system.apply_to(&[2, 0], &Matrix::cnot());
+        assert_eq!(system.measure(), vec![1, 0, 1]);
+    }
+
+    #[test]
+    pub fn controlled_in_place() {
+        let values: Vec<ComplexNumber> =
+            (0..32).map(|n| c!((n as f64).sin(), (n as f64 * 0.3).cos())).collect();
+        let none = || Gate::Other(String::from("none"));
+
+        let mut dense = QubitSystem::from_tensor(values.clone(), 5);
+        dense.apply_gates(vec![Gate::I, Gate::CNOT, none(), Gate::I, Gate::I]);
+        let mut sparse = QubitSystem::from_tensor(values.clone(), 5);
+        sparse.apply_controlled(1, 2, &Matrix::pauli_x());
+        assert_eq!(sparse.get_values(), dense.get_values());
+
+        let mut dense = QubitSystem::from_tensor(values.clone(), 5);
+        dense.apply_to(&[4, 0], &Matrix::cnot());
+        let mut sparse = QubitSystem::from_tensor(values, 5);
+        sparse.apply_controlled(4, 0, &Matrix::pauli_x());
+        assert_eq!(sparse.get_values(), dense.get_values());
+    }
+
+    #[test]
+    pub fn distant_cswap() {
+        let values: Vec<ComplexNumber> = (0..16).map(|n| c!(n as f64)).collect();
+        let mut system = QubitSystem::from_tensor(values, 4);
+        system.apply_cswap(0, 1, 3);
+
+        // With qubit 0 set, |1100⟩ (12) and |1001⟩ (9) trade places, and so do 14 and 11
+        let expected: Vec<ComplexNumber> = [0, 1, 2, 3, 4, 5, 6, 7, 8, 12, 10, 14, 9, 13, 11, 15]
+            .iter()
+            .map(|n| c!(*n as f64))
+            .collect();
+        assert_eq!(system.get_values(), expected);
+
+        let mut dense = QubitSystem::new(vec![Qubit::one(), Qubit::one(), Qubit::zero()]);
+        dense.apply_cswap(0, 1, 2);
+        assert_eq!(dense.measure(), vec![1, 0, 1]);
+    }
+
+    #[test]
+    pub fn anti_cnot() {
+        let mut system = QubitSystem::new(vec![Qubit::zero(), Qubit::zero()]);
+        system.apply(&Gate::ACNOT, &[0, 1]);
+        assert_eq!(system.measure(), vec![0, 1]);
+
+        let mut system = QubitSystem::new(vec![Qubit::one(), Qubit::zero()]);
+        system.apply_gates(vec![Gate::ACNOT, Gate::Other(String::from("none"))]);
+        assert_eq!(system.measure(), vec![1, 0]);
+    }
+
+    #[test]
+    pub fn diagonal_phases() {
+        let plus = Qubit::zero().hadamard();
+        let mut system = QubitSystem::new(vec![plus.clone(), plus]);
+        system.apply(&Gate::DiagonalPhase(vec![0.0, 0.0, 0.0, PI]), &[0, 1]);
+        assert_eq!(system.get_values(), vec![c!(0.5), c!(0.5), c!(0.5), c!(-0.5)]);
+
+        // A zero angle on |11⟩ leaves everything alone
+        system.apply(&Gate::DiagonalPhase(vec![0.0, 0.0, 0.0, 0.0]), &[0, 1]);
+        assert_eq!(system.get_values(), vec![c!(0.5), c!(0.5), c!(0.5), c!(-0.5)]);
+
+        let mut cached = QubitSystem::new(vec![Qubit::one(), Qubit::one()]);
+        cached.apply_column_fast(&[Gate::DiagonalPhase(vec![0.0, 0.0, 0.0, PI / 2.0]), Gate::I]);
+        assert_eq!(cached.get_values()[3], c!(0.0, 1.0));
+    }
+
+    #[test]
+    #[should_panic(expected = "power of two")]
+    pub fn uneven_diagonal_phase() {
+        Gate::DiagonalPhase(vec![0.0, 0.0, 0.0]).to_matrix();
+    }
+
+    #[test]
+    pub fn basis_amplitudes() {
+        let mut system = QubitSystem::new(vec![Qubit::one(), Qubit::zero()]);
+        system.apply_gate(0, Matrix::hadamard());
+        system.apply_gate(0, Matrix::cnot());
+
+        let half = ComplexNumber::SQRT_HALF;
+        assert_eq!(system.amplitude(&[0, 0]), half);
+        assert_eq!(system.amplitude(&[1, 1]), c!(-1.0) * half);
+        assert_eq!(system.amplitude(&[0, 1]), c!(0.0));
+        assert!((system.probability(&[1, 1]) - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    pub fn outcome_distribution() {
+        let system = QubitSystem::new(vec![Qubit::zero().hadamard()]);
+        assert!(system.probabilities().iter().all(|p| (p - 0.5).abs() < 1e-12));
+        assert_eq!(system.probabilities().len(), 2);
+
+        let system = QubitSystem::new(vec![Qubit::one(), Qubit::zero()]);
+        assert_eq!(system.index_probability(0b10), 1.0);
+        assert_eq!(system.index_probability(0b10), system.probability(&[1, 0]));
+    }
+
+    #[test]
+    pub fn amplitude_iterator() {
+        let system = QubitSystem::new(vec![Qubit::one(), Qubit::zero().hadamard()]);
+        let half = ComplexNumber::SQRT_HALF;
+
+        assert_eq!(
+            system.iter_amplitudes().collect::<Vec<_>>(),
+            vec![
+                (vec![0, 0], c!(0.0)),
+                (vec![0, 1], c!(0.0)),
+                (vec![1, 0], half),
+                (vec![1, 1], half),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn gate_params() {
+        let gate = Gate::U3(0.1, 0.2, 0.3);
+        assert_eq!(gate.params(), vec![0.1, 0.2, 0.3]);
+        assert_eq!(gate.with_param(1, 0.7), Gate::U3(0.1, 0.7, 0.3));
+        assert_eq!(gate.with_param(3, 0.7), gate);
+        assert_eq!(Gate::RY(0.4).with_param(0, 0.6), Gate::RY(0.6));
+        assert_eq!(Gate::H.with_param(0, 0.6), Gate::H);
+
+        // Different parameters of the same gate get their own cached matrix
+        let other = gate.with_param(2, 0.5);
+        assert!(gate.cached_matrix().approx_eq(&gate.to_matrix(), 1e-12));
+        assert!(other.cached_matrix().approx_eq(&other.to_matrix(), 1e-12));
+        assert!(!gate.cached_matrix().approx_eq(&other.cached_matrix(), 1e-3));
+    }
+
+    #[test]
+    pub fn measure_and_reset() {
+        let mut system = QubitSystem::new(vec![Qubit::one(), Qubit::one()]);
+        assert_eq!(system.measure_reset(0), 1);
+        assert_eq!(system.get_values(), vec![c!(0.0), c!(1.0), c!(0.0), c!(0.0)]);
+        assert_eq!(system.measure_reset(0), 0);
+
+        // The measured qubit's partner stays collapsed to match it, only the qubit is reset
+        let mut bell = QubitSystem::new(vec![Qubit::zero().hadamard(), Qubit::zero()]);
+        bell.apply(&Gate::CNOT, &[0, 1]);
+        bell.apply(&Gate::MeasureReset, &[0]);
+        let outcome = bell.measure_single(1);
+        assert_eq!(bell.measure(), vec![0, outcome]);
+    }
+
+    #[test]
+    pub fn measurement_bases() {
+        for _ in 0..20 {
+            let mut plus = QubitSystem::new(vec![Qubit::zero().hadamard()]);
+            assert_eq!(plus.measure_single_basis(0, Basis::X), 0);
+            assert_eq!(plus.measure_single_basis(0, Basis::X), 0);
+
+            let mut minus = QubitSystem::new(vec![Qubit::one().hadamard()]);
+            assert_eq!(minus.measure_single_basis(0, Basis::X), 1);
+
+            let mut plus_i = QubitSystem::new(vec![Qubit::zero().hadamard()]);
+            plus_i.apply(&Gate::S, &[0]);
+            assert_eq!(plus_i.measure_single_basis(0, Basis::Y), 0);
+            plus_i.apply(&Gate::Z, &[0]);
+            assert_eq!(plus_i.measure_single_basis(0, Basis::Y), 1);
+
+            let mut one = QubitSystem::new(vec![Qubit::zero(), Qubit::one()]);
+            assert_eq!(one.measure_single_basis(1, Basis::Z), 1);
+        }
+
+        // Reading |0⟩ along X leaves it in |+⟩ or |-⟩, which then read the same every time
+        let mut zero = QubitSystem::new(vec![Qubit::zero()]);
+        let outcome = zero.measure_single_basis(0, Basis::X);
+        assert!((zero.outcome_probability(0, 1) - 0.5).abs() < 1e-9);
+        assert_eq!(zero.measure_single_basis(0, Basis::X), outcome);
+    }
+
+    #[test]
+    pub fn reset_qubits() {
+        for _ in 0..10 {
+            let qubits = vec![Qubit::one(), Qubit::zero().hadamard(), Qubit::zero()];
+            let mut system = QubitSystem::new(qubits);
+            system.apply(&Gate::CNOT, &[1, 2]);
+            system.reset_qubit(0);
+
+            // The Bell pair on the other two qubits is untouched
+            assert_eq!(system.measure_single(0), 0);
+            assert!((system.outcome_probability(1, 1) - 0.5).abs() < 1e-9);
+            let outcome = system.measure_single(1);
+            assert_eq!(system.measure(), vec![0, outcome, outcome]);
+        }
+    }
+
+    #[test]
+    pub fn gate_on_every_qubit() {
+        let values: Vec<ComplexNumber> = (0..32).map(|n| c!(n as f64, (n % 3) as f64)).collect();
+        let mut system = QubitSystem::from_tensor(values, 5);
+        system.renormalize();
+        let mut tensored = system.clone();
+
+        system.apply_gate_all(Matrix::hadamard());
+        let full_gate =
+            (0..5).fold(matrix_new!([c!(1.0)]), |acc, _| acc.tensor(&Matrix::hadamard()));
+        tensored.apply_full_gate(full_gate);
+
+        for (a, b) in system.get_values().iter().zip(tensored.get_values()) {
+            assert!(a.approx_eq(&b, 1e-9), "{a:?} != {b:?}");
+        }
+    }
+
+    #[test]
+    pub fn builtin_gates() {
+        // Exhaustive, so a new variant won't compile until it's numbered here
+        let variant = |gate: &Gate| match gate {
+            Gate::I => 0,
+            Gate::X => 1,
+            Gate::Y => 2,
+            Gate::Z => 3,
+            Gate::H => 4,
+            Gate::M => 5,
+            Gate::MeasureReset => 6,
+            Gate::P(_) => 7,
+            Gate::S => 8,
+            Gate::Sdg => 9,
+            Gate::T => 10,
+            Gate::Tdg => 11,
+            Gate::RX(_) => 12,
+            Gate::RY(_) => 13,
+            Gate::RZ(_) => 14,
+            Gate::U3(..) => 15,
+            Gate::Rot(..) => 16,
+            Gate::CNOT => 17,
+            Gate::ACNOT => 18,
+            Gate::CZ => 19,
+            Gate::CP(_) => 20,
+            Gate::SWAP => 21,
+            Gate::CCX => 22,
+            Gate::CCCX => 23,
+            Gate::CSWAP => 24,
+            Gate::CCZ => 25,
+            Gate::Controlled(_) => 26,
+            Gate::DiagonalPhase(_) => 27,
+            Gate::Init(_) => 28,
+            Gate::Assert(_) => 29,
+            Gate::Other(_) => 30,
+        };
+
+        let mut variants: Vec<usize> = Gate::all_builtin().iter().map(variant).collect();
+        variants.sort();
+        assert_eq!(variants, (0..30).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    pub fn state_diff() {
+        let plus = QubitSystem::new(vec![Qubit::zero().hadamard(), Qubit::zero().hadamard()]);
+        assert!(diff_states(&plus, &plus, 1e-9).is_empty());
+
+        let mut shifted = plus.clone();
+        shifted.apply_gate(1, Matrix::pauli_z());
+        assert_eq!(
+            diff_states(&plus, &shifted, 1e-9),
+            vec![(vec![0, 1], c!(0.5), c!(-0.5)), (vec![1, 1], c!(0.5), c!(-0.5))]
+        );
+    }
+
+    #[test]
+    pub fn ket_matching() {
+        let system = QubitSystem::new(vec![Qubit::one(), Qubit::zero()]);
+        assert!(system.matches_ket("10"));
+        assert!(system.matches_ket("|10⟩"));
+        assert!(system.matches_ket("|10>"));
+        assert!(!system.matches_ket("01"));
+        assert!(!system.matches_ket("1"));
+        assert!(!system.matches_ket("1x"));
+
+        let plus = QubitSystem::new(vec![Qubit::zero().hadamard()]);
+        assert!(!plus.matches_ket("0") && !plus.matches_ket("1"));
+    }
+
+    #[test]
+    pub fn cnot_chain() {
+        let mut system =
+            QubitSystem::new(vec![Qubit::one(), Qubit::zero(), Qubit::zero(), Qubit::zero()]);
+        system.apply_layer(&Gate::CNOT, &[(0, 1), (1, 2), (2, 3)]);
+        assert_eq!(system.measure(), vec![1, 1, 1, 1]);
+    }
+
+    #[test]
+    pub fn apply_by_gate() {
+        let mut system = QubitSystem::new(vec![Qubit::one(), Qubit::zero(), Qubit::zero()]);
+        system.apply(&Gate::CNOT, &[0, 2]);
+        assert_eq!(system.measure(), vec![1, 0, 1]);
+
+        system.apply(&Gate::X, &[1]);
+        system.apply(&Gate::I, &[0]);
+        assert_eq!(system.measure(), vec![1, 1, 1]);
+    }
+
+    #[test]
+    pub fn subset_measure() {
+        for _ in 0..10 {
+            let mut ghz = QubitSystem::new(vec![Qubit::zero(); 3]);
+            ghz.apply_gate(0, Matrix::hadamard());
+            ghz.apply_to(&[0, 1], &Matrix::cnot());
+            ghz.apply_to(&[1, 2], &Matrix::cnot());
+
+            let outcomes = ghz.measure_subset(&[0, 2]);
+            assert_eq!(outcomes[0], outcomes[1]);
+            assert!(ghz.system_normal());
+            assert_eq!(ghz.measure_single(1), outcomes[0]);
+        }
+    }
+
+    #[test]
+    pub fn matrix_cache() {
+        for gate in [Gate::H, Gate::CNOT, Gate::RX(0.4), Gate::P(1.3)] {
+            assert_eq!(*gate.cached_matrix(), gate.to_matrix());
+            assert!(Rc::ptr_eq(&gate.cached_matrix(), &gate.cached_matrix()));
+        }
+
+        assert_ne!(*Gate::RY(0.4).cached_matrix(), *Gate::RY(1.4).cached_matrix());
+        assert_ne!(*Gate::RY(0.4).cached_matrix(), *Gate::RZ(0.4).cached_matrix());
+    }
+
+    #[test]
+    pub fn count_probabilities() {
+        let counts = HashMap::from([(vec![0, 0], 250), (vec![1, 1], 700), (vec![0, 1], 50)]);
+        let probabilities = counts_to_probabilities(&counts);
+
+        assert_eq!(probabilities.len(), 3);
+        assert_eq!(probabilities[&vec![0, 0]], 0.25);
+        assert_eq!(probabilities[&vec![1, 1]], 0.7);
+        assert_eq!(probabilities[&vec![0, 1]], 0.05);
+    }
+
+    #[test]
+    pub fn sampling() {
+        let mut system = QubitSystem::new(vec![Qubit::zero(), Qubit::zero()]);
+        system.apply_gate(0, Matrix::hadamard());
+        system.apply_gate(0, Matrix::cnot());
+
+        let counts = system.sample(1000);
+        assert_eq!(counts.values().sum::<usize>(), 1000);
+        assert!(counts.keys().all(|state| *state == vec![0, 0] || *state == vec![1, 1]));
+
+        let probabilities = counts_to_probabilities(&counts);
+        assert!((probabilities[&vec![0, 0]] - 0.5).abs() < 0.1);
+    }
+
+    #[test]
+    pub fn trace_out_many() {
+        let mut ghz = QubitSystem::new(vec![Qubit::zero(); 3]);
+        ghz.apply_gate(0, Matrix::hadamard());
+        ghz.apply_to(&[0, 1], &Matrix::cnot());
+        ghz.apply_to(&[1, 2], &Matrix::cnot());
+        let density = ghz.density_matrix();
+
+        let iterated = partial_trace(partial_trace(density.clone(), 0, 3), 0, 2);
+        let reduced = partial_trace_out(&density, &[1, 2], 3);
+        assert_eq!(reduced, iterated);
+        assert_eq!(reduced, matrix_new!([c!(0.5), c!(0.0)], [c!(0.0), c!(0.5)]));
+    }
+
+    #[test]
+    pub fn plus_density() {
+        let system = QubitSystem::new(vec![Qubit::zero().hadamard()]);
+        let density = system.density_matrix();
+
+        for i in 0..2 {
+            for j in 0..2 {
+                assert!(density[i][j].approx_eq(&c!(0.5), 1e-12));
+            }
+        }
+    }
+
+    #[test]
+    pub fn plus_i_density() {
+        let mut system = QubitSystem::new(vec![Qubit::zero().hadamard()]);
+        system.apply(&Gate::S, &[0]);
+        let density = system.density_matrix();
+
+        // |+i⟩⟨+i| has -i/2 above the diagonal and i/2 below it
+        assert!(density[0][1].approx_eq(&c!(0.0, -0.5), 1e-12));
+        assert!(density[1][0].approx_eq(&c!(0.0, 0.5), 1e-12));
+
+        let [x, y, z] = system.bloch_vector_for(0);
+        assert!(x.abs() < 1e-9 && (y - 1.0).abs() < 1e-9 && z.abs() < 1e-9);
+    }
+
+    #[test]
+    pub fn trace_out_order() {
+        let system = QubitSystem::new(vec![Qubit::zero(), Qubit::zero().hadamard(), Qubit::one()]);
+        let density = system.density_matrix();
+
+        let first = partial_trace_out(&density, &[1, 2], 3);
+        let middle = partial_trace_out(&density, &[2, 0], 3);
+        let last = partial_trace_out(&density, &[0, 1], 3);
+
+        assert_eq!(first, matrix_new!([c!(1.0), c!(0.0)], [c!(0.0), c!(0.0)]));
+        assert_eq!(middle, matrix_new!([c!(0.5), c!(0.5)], [c!(0.5), c!(0.5)]));
+        assert_eq!(last, matrix_new!([c!(0.0), c!(0.0)], [c!(0.0), c!(1.0)]));
+
+        let expected = [[0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0]];
+        for (vector, expected) in system.bloch_vectors().iter().zip(expected) {
+            assert!(vector.iter().zip(expected).all(|(a, b)| (a - b).abs() < 1e-9), "{vector:?}");
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "qubit 3 out of range")]
+    pub fn trace_out_invalid() {
+        let system = QubitSystem::new(vec![Qubit::zero(); 2]);
+        partial_trace_out(&system.density_matrix(), &[3], 2);
+    }
+
+    #[test]
+    pub fn qubit_order() {
+        let states = [
+            (vec![Qubit::zero(), Qubit::zero(), Qubit::one()], [0, 0, 1], 1),
+            (vec![Qubit::zero(), Qubit::one(), Qubit::zero()], [0, 1, 0], 2),
+            (vec![Qubit::one(), Qubit::zero(), Qubit::zero()], [1, 0, 0], 4),
+        ];
+
+        for (qubits, bits, idx) in states {
+            let mut system = QubitSystem::new(qubits);
+            assert_eq!(system.index_of_basis(&bits), idx);
+            assert_eq!(system.get_values()[idx], c!(1.0));
+            assert_eq!(system.argmax_state().0, bits);
+            assert_eq!(system.measure_subset(&[0, 1, 2]), bits);
+            assert_eq!(system.measure(), bits);
+        }
+
+        for target in 0..4 {
+            let mut bits = vec![0; 4];
+            bits[target] = 1;
+
+            let mut dense = QubitSystem::new(vec![Qubit::zero(); 4]);
+            let mut fast = QubitSystem::new(vec![Qubit::zero(); 4]);
+            dense.apply_gate(target, Matrix::pauli_x());
+            fast.apply_single(target, &Matrix::pauli_x());
+
+            assert_eq!(dense.get_values()[dense.index_of_basis(&bits)], c!(1.0));
+            assert_eq!(dense.get_values(), fast.get_values());
+        }
+
+        let mut system = QubitSystem::new(vec![Qubit::zero(); 4]);
+        system.apply_gate(2, Matrix::pauli_x());
+        system.apply_gate(2, Matrix::cnot());
+        assert_eq!(system.measure(), vec![0, 0, 1, 1]);
+    }
+
+    #[test]
+    pub fn system_fidelity() {
+        let zeros = QubitSystem::new(vec![Qubit::zero(), Qubit::zero()]);
+        let ones = QubitSystem::new(vec![Qubit::one(), Qubit::one()]);
+        let mut bell = QubitSystem::new(vec![Qubit::zero(), Qubit::zero()]);
+        bell.apply_gate(0, Matrix::hadamard());
+        bell.apply_gate(0, Matrix::cnot());
+
+        assert!((zeros.fidelity(&zeros) - 1.0).abs() < 1e-9);
+        assert!((bell.fidelity(&bell) - 1.0).abs() < 1e-9);
+        assert!(zeros.fidelity(&ones).abs() < 1e-9);
+        assert!((bell.fidelity(&zeros) - 0.5).abs() < 1e-9);
+        assert!((bell.fidelity(&ones) - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    #[should_panic(expected = "same size")]
+    pub fn fidelity_mismatch() {
+        let one = QubitSystem::new(vec![Qubit::zero()]);
+        let two = QubitSystem::new(vec![Qubit::zero(), Qubit::zero()]);
+        one.fidelity(&two);
+    }
+
+    #[test]
+    pub fn pauli_expectation() {
+        let mut bell = QubitSystem::new(vec![Qubit::zero(), Qubit::zero()]);
+        bell.apply_gate(0, Matrix::hadamard());
+        bell.apply_gate(0, Matrix::cnot());
+
+        assert!((bell.expectation_pauli("ZZ") - 1.0).abs() < 1e-9);
+        assert!((bell.expectation_pauli("XX") - 1.0).abs() < 1e-9);
+        assert!(bell.expectation_pauli("XI").abs() < 1e-9);
+        assert!(bell.expectation_pauli("ZI").abs() < 1e-9);
+
+        let one = QubitSystem::new(vec![Qubit::one()]);
+        assert!((one.expectation(&Matrix::pauli_z()) + 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one qubit")]
+    pub fn empty_system() {
+        QubitSystem::new(Vec::new());
+    }
+
+    #[test]
+    pub fn checked_errors() {
+        let mut system = QubitSystem::new(vec![Qubit::zero(); 2]);
+        let half = ComplexNumber::SQRT_HALF;
+
+        assert_eq!(
+            system.set_values(vec![c!(1.0), c!(0.0)]),
+            Err(QuantumError::DimensionMismatch { expected: 4, found: 2 })
+        );
+        assert!(matches!(
+            system.set_values(vec![c!(1.0), c!(1.0), c!(0.0), c!(0.0)]),
+            Err(QuantumError::NotNormalized(norm)) if (norm - 2.0).abs() < 1e-9
+        ));
+        assert_eq!(system.set_values(vec![half, c!(0.0), c!(0.0), half]), Ok(()));
+
+        assert_eq!(
+            system.try_apply_full_gate(Matrix::hadamard()),
+            Err(QuantumError::DimensionMismatch { expected: 4, found: 2 })
+        );
+        let doubled = Matrix::cnot().scale(c!(2.0));
+        assert_eq!(system.try_apply_full_gate(doubled), Err(QuantumError::NonUnitary));
+        assert_eq!(system.try_apply_full_gate(Matrix::cnot()), Ok(()));
+        assert_eq!(system.get_values(), vec![half, c!(0.0), half, c!(0.0)]);
+
+        assert_eq!(
+            system.try_apply_gate(2, Matrix::pauli_x()),
+            Err(QuantumError::InvalidQubitIndex { qubit: 2, len: 2 })
+        );
+        assert!(matches!(
+            system.try_apply_gate(1, Matrix::cnot()),
+            Err(QuantumError::DimensionMismatch { .. })
+        ));
+        assert_eq!(QubitSystem::try_new(Vec::new()).err(), Some(QuantumError::EmptySystem));
+    }
+
+    #[test]
+    #[should_panic(expected = "empty tensor")]
+    pub fn empty_tensor() {
+        tensor_product(Vec::new(), vec![c!(1.0)]);
+    }
+
+    #[test]
+    pub fn real_preparation() {
+        let system = QubitSystem::prepare_real(&[0.6, 0.8]);
+        assert_eq!(system.get_values(), vec![c!(0.6), c!(0.8)]);
+
+        let amplitudes = [1.0, -2.0, 0.0, 3.0, 0.5, 0.0, -1.5, 2.0];
+        let norm = amplitudes.iter().map(|a| a * a).sum::<f64>().sqrt();
+        let system = QubitSystem::prepare_real(&amplitudes);
+        for (value, amplitude) in system.get_values().iter().zip(amplitudes) {
+            assert!((value.real - amplitude / norm).abs() < 1e-9);
+            assert!(value.imaginary.abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    pub fn ry_rotation() {
+        let mut system = QubitSystem::new(vec![Qubit::zero()]);
+        system.apply_gate(0, Matrix::ry(PI / 2.0));
+        assert_eq!(system.get_values(), vec![ComplexNumber::SQRT_HALF, ComplexNumber::SQRT_HALF]);
+        assert!(system.system_normal());
+    }
+
+    #[test]
+    pub fn estimated_expectation() {
+        let mut rng = StdRng::seed_from_u64(1398);
+
+        let mut system = QubitSystem::new(vec![Qubit::zero(), Qubit::zero()]);
+        system.apply_gate(0, Matrix::rx(0.7));
+        system.apply_gate(1, Matrix::ry(1.9));
+        system.apply_to(&[0, 1], &Matrix::cnot());
+
+        for observable in ["ZI", "YI", "XZ", "YY"] {
+            let matrix = pauli_string_matrix(observable);
+            let exact = system.expectation(&matrix);
+            let (estimate, error) = system.estimate_expectation(&matrix, 20000, &mut rng);
+
+            assert!(error > 0.0 && error < 0.01, "{observable}: {error}");
+            assert!((estimate - exact).abs() < 4.0 * error, "{observable}: {estimate} {exact}");
+        }
+
+        // A state in the observable's eigenbasis always gives the same outcome
+        let one = QubitSystem::new(vec![Qubit::one()]);
+        assert_eq!(one.estimate_expectation(&Matrix::pauli_z(), 100, &mut rng), (-1.0, 0.0));
+    }
+
+    #[test]
+    pub fn single_bloch_vector() {
+        let mut system = QubitSystem::new(vec![Qubit::zero(), Qubit::zero()]);
+        system.apply_gate(1, Matrix::rx(0.7));
+
+        let vector = system.bloch_vector_for(1);
+        let expected = [0.0, -(0.7f64.sin()), 0.7f64.cos()];
+        for (a, b) in vector.iter().zip(expected) {
+            assert!((a - b).abs() < 1e-9);
+        }
+
+        let traced = bloch_vector(partial_trace_out(&system.density_matrix(), &[0], 2));
+        assert_eq!(vector.to_vec(), traced);
+        assert_eq!(system.bloch_vector_for(0), [0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    pub fn pure_bloch_vectors() {
+        let cases = [
+            (Qubit::zero(), [0.0, 0.0, 1.0]),
+            (Qubit::one(), [0.0, 0.0, -1.0]),
+            (Qubit::zero().hadamard(), [1.0, 0.0, 0.0]),
+        ];
+        for (qubit, expected) in cases {
+            let density = QubitSystem::new(vec![qubit]).density_matrix();
+            let vector = bloch_vector_checked(density, 1e-9).unwrap();
+            assert!(vector.iter().zip(expected).all(|(a, b)| (a - b).abs() < 1e-9));
+        }
+
+        // Twice |0⟩⟨0| has trace 2, far past the unit sphere
+        let doubled = matrix_new!([c!(2.0), c!(0.0)], [c!(0.0), c!(0.0)]);
+        assert_eq!(bloch_vector_checked(doubled, 1e-9), Err(QuantumError::OutsideBlochSphere(2.0)));
+        let full = QubitSystem::new(vec![Qubit::zero(); 2]).density_matrix();
+        assert_eq!(
+            bloch_vector_checked(full, 1e-9),
+            Err(QuantumError::DimensionMismatch { expected: 2, found: 4 })
+        );
+    }
+
+    #[test]
+    pub fn qubit_purity() {
+        let mut system = QubitSystem::new(vec![Qubit::zero(), Qubit::zero(), Qubit::zero()]);
+        system.apply_gate(0, Matrix::hadamard());
+        system.apply_gate(2, Matrix::rx(0.9));
+        assert!((purity(&system.density_matrix()) - 1.0).abs() < 1e-9);
+
+        system.apply_to(&[0, 1], &Matrix::cnot());
+        let purities = system.qubit_purities();
+        assert!((purities[0] - 0.5).abs() < 1e-9);
+        assert!((purities[1] - 0.5).abs() < 1e-9);
+        assert!((purities[2] - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    pub fn affected_states() {
+        assert_eq!(affected_basis_states(&Gate::CNOT, &[0, 1], 2), vec![2, 3]);
+        assert_eq!(affected_basis_states(&Gate::CNOT, &[1, 0], 2), vec![1, 3]);
+        assert_eq!(affected_basis_states(&Gate::Z, &[0], 2), vec![2, 3]);
+        assert_eq!(affected_basis_states(&Gate::H, &[1], 2), vec![0, 1, 2, 3]);
+        assert_eq!(affected_basis_states(&Gate::M, &[1], 2), vec![0, 1, 2, 3]);
+        assert!(affected_basis_states(&Gate::I, &[0], 2).is_empty());
+        assert!(affected_basis_states(&Gate::Other(String::from("adder")), &[0], 2).is_empty());
+    }
+
+    #[test]
+    pub fn column_operators() {
+        let none = Gate::Other(String::from("none"));
+        let column = [Gate::H, Gate::CNOT, none, Gate::I, Gate::T];
+        let expected = Matrix::hadamard()
+            .tensor(&Matrix::cnot())
+            .tensor(&Matrix::identity2())
+            .tensor(&Matrix::t());
+        assert!(QubitSystem::column_operator(&column).approx_eq(&expected, 1e-12));
+
+        let idle = QubitSystem::column_operator(&[Gate::I, Gate::M, Gate::I]);
+        assert!(idle.approx_eq(&Matrix::identity(8), 1e-12));
+    }
+
+    #[test]
+    pub fn qubit_expectation() {
+        let z = Matrix::pauli_z();
+        assert!((Qubit::zero().expectation(&z) - 1.0).abs() < 1e-12);
+        assert!((Qubit::one().expectation(&z) + 1.0).abs() < 1e-12);
+        assert!(Qubit::zero().hadamard().expectation(&z).abs() < 1e-12);
+        assert!((Qubit::zero().hadamard().expectation(&Matrix::pauli_x()) - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    pub fn system_purity() {
+        let single = QubitSystem::new(vec![Qubit::zero().hadamard()]);
+        assert!((single.purity() - 1.0).abs() < 1e-9);
+        assert!((single.fidelity(&single) - 1.0).abs() < 1e-9);
+
+        // The Bell state itself is pure, but either half of it is maximally mixed
+        let mut bell = QubitSystem::new(vec![Qubit::zero().hadamard(), Qubit::zero()]);
+        bell.apply_to(&[0, 1], &Matrix::cnot());
+        assert!((bell.purity() - 1.0).abs() < 1e-9);
+        assert!((purity(&partial_trace_out(&bell.density_matrix(), &[1], 2)) - 0.5).abs() < 1e-9);
+        assert!((bell.fidelity(&bell) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    pub fn global_phase() {
+        let mut system = QubitSystem::new(vec![Qubit::zero().hadamard(), Qubit::zero()]);
+        assert!(system.get_global_phase().approx_eq(&c!(1.0), 1e-12));
+
+        system.apply_gates(vec![Gate::RZ(PI / 2.0), Gate::T]);
+        assert!(system.get_global_phase().approx_eq(&c!(0.0, PI / 4.0).exp(), 1e-12));
+
+        system.apply_column_fast(&[Gate::H, Gate::RZ(PI / 2.0)]);
+        assert!(system.get_global_phase().approx_eq(&c!(0.0, 1.0), 1e-12));
+
+        // A Rot about +z undoes an RZ by the same angle, global phase included
+        system.apply_gates(vec![Gate::I, Gate::Rot([0.0, 0.0, 2.0], PI / 2.0)]);
+        assert!(system.get_global_phase().approx_eq(&c!(0.0, PI / 4.0).exp(), 1e-12));
+
+        // RZ(θ) and Rot about ±z really are phase gates scaled by the tracked phase
+        for (gate, phase) in [
+            (Gate::RZ(0.8), -0.8),
+            (Gate::Rot([0.0, 0.0, 1.0], 0.8), 0.8),
+            (Gate::Rot([0.0, 0.0, -3.0], 0.8), -0.8),
+        ] {
+            let expected = Matrix::phase(phase).scale(gate.global_phase());
+            assert!(expected.approx_eq(&gate.to_matrix(), 1e-12), "{gate:?}");
+        }
+        assert_eq!(Gate::Rot([1.0, 0.0, 1.0], 0.8).global_phase(), c!(1.0));
+    }
+
+    #[test]
+    pub fn display_kets() {
+        let mut bell = QubitSystem::new(vec![Qubit::zero(), Qubit::zero()]);
+        bell.apply_gate(0, Matrix::hadamard());
+        bell.apply_gate(0, Matrix::cnot());
+        assert_eq!(bell.to_string(), "1/√2 |00⟩, 1/√2 |11⟩");
+
+        let system = QubitSystem::new(vec![Qubit::one(), Qubit::zero(), Qubit::zero()]);
+        assert_eq!(system.to_string(), "1.00 |100⟩");
+    }
+
+    #[test]
+    pub fn bell_tree() {
+        let mut bell = QubitSystem::new(vec![Qubit::zero(), Qubit::zero()]);
+        bell.apply_gate(0, Matrix::hadamard());
+        bell.apply_gate(0, Matrix::cnot());
+        let tree = bell.measurement_tree(&[0, 1]);
+
+        assert_eq!(tree.branches.len(), 2);
+        for (idx, (outcome, probability, subtree)) in tree.branches.iter().enumerate() {
+            assert_eq!(*outcome, idx);
+            assert!((probability - 0.5).abs() < 1e-9);
+
+            assert_eq!(subtree.branches.len(), 1);
+            let (second, probability, leaf) = &subtree.branches[0];
+            assert_eq!(second, outcome);
+            assert!((probability - 0.5).abs() < 1e-9);
+            assert!(leaf.branches.is_empty());
+        }
+    }
+
+    #[test]
+    pub fn follow_bell_path() {
+        let mut bell = QubitSystem::new(vec![Qubit::zero(), Qubit::zero()]);
+        bell.apply_gate(0, Matrix::hadamard());
+        bell.apply_gate(0, Matrix::cnot());
+
+        assert!(bell.follow_path(&[0, 1], &[1, 0]).is_err());
+        assert!(bell.follow_path(&[0, 2], &[1, 1]).is_err());
+        assert!(bell.follow_path(&[0], &[1, 1]).is_err());
+
+        let probability = bell.follow_path(&[0, 1], &[1, 1]).unwrap();
+        assert!((probability - 0.5).abs() < 1e-9);
+        assert!(bell.matches_ket("11"));
+    }
+
     #[test]
     pub fn single_measure() {
         let mut system = QubitSystem::new(vec![Qubit::zero(), Qubit::one(), Qubit::zero()]);
@@ -0,0 +1,843 @@
+use std::rc::Rc;
+
+use crate::circuit::{
+    AngleWire, ColumnTiming, apply_stored_wires, step_column_adaptive, step_column_profiled,
+};
+use crate::complex::ComplexNumber;
+use crate::matrix::Matrix;
+use crate::qubit::{Gate, Qubit, QubitSystem};
+
+// What happened in one step of a Simulator, so a UI can show measurements as they happen
+#[derive(Debug, PartialEq)]
+pub struct StepOutcome {
+    pub column: usize,
+    // (qubit, outcome, probability the outcome had), in the order they were measured
+    pub measurements: Vec<(usize, usize, f64)>,
+    // (register, passed) for every Assert gate in the column, checked before its other gates
+    pub assertions: Vec<(usize, bool)>,
+    // (register, name) for every function too tall to fit below its register (or nested too
+    // deeply in other functions), which were left as identities instead
+    pub skipped_functions: Vec<(usize, String)>,
+}
+
+// How deep saved gates can be nested inside each other, which also stops a function that
+// uses itself from running forever
+pub const MAX_FUNCTION_DEPTH: usize = 16;
+
+// A circuit and the state of stepping through it, with no UI attached
+// Gates are stored as columns holding one gate per qubit, multi-qubit gates fill the cells
+// below them with Other("none"). Wires are (column, measured qubit, conditioned qubit)
+pub struct Simulator {
+    system: QubitSystem,
+    // The state of each qubit before the first column, |0⟩ unless set_inputs changed it
+    inputs: Vec<Qubit>,
+    pub gates: Vec<Vec<Gate>>,
+    pub wires: Vec<(usize, usize, usize)>,
+    pub angle_wires: Vec<AngleWire>,
+    registers: usize,
+    pub step: usize,
+    functions: Vec<(String, Vec<Vec<Gate>>)>,
+    history: Vec<QubitSystem>,
+    // Times each step's operator build and apply when set, off by default
+    pub profiling: bool,
+    timings: Vec<(usize, ColumnTiming)>,
+    // Each column's full operator, built the first time a column without measurements or
+    // wires is stepped and reused on replays. Edits through the methods below clear it
+    operators: Vec<Option<Rc<Matrix>>>,
+    // Latest measured value of each qubit, read by wires from earlier columns
+    classical: Vec<Option<usize>>,
+}
+
+impl Simulator {
+    // An empty circuit of one column on the given number of qubits, all starting at |0⟩
+    pub fn new(registers: usize) -> Self {
+        Self {
+            system: QubitSystem::new(vec![Qubit::zero(); registers]),
+            inputs: vec![Qubit::zero(); registers],
+            gates: vec![vec![Gate::I; registers]],
+            wires: Vec::new(),
+            angle_wires: Vec::new(),
+            registers,
+            step: 0,
+            functions: Vec::new(),
+            history: Vec::new(),
+            profiling: false,
+            timings: Vec::new(),
+            operators: Vec::new(),
+            classical: vec![None; registers],
+        }
+    }
+
+    pub fn from_circuit(gates: Vec<Vec<Gate>>, wires: Vec<(usize, usize, usize)>) -> Self {
+        let mut simulator = Self::new(gates.first().map_or(0, Vec::len).max(1));
+        simulator.load(gates, wires);
+        simulator
+    }
+
+    // Replaces the circuit and starts again from the beginning, with every input at |0⟩
+    // A circuit without any columns or qubits loads as the blank one qubit circuit of new(1)
+    pub fn load(&mut self, gates: Vec<Vec<Gate>>, wires: Vec<(usize, usize, usize)>) {
+        let (gates, wires) = match gates.first().map_or(0, Vec::len) {
+            0 => (vec![vec![Gate::I]], Vec::new()),
+            _ => (gates, wires),
+        };
+        self.registers = gates[0].len();
+        self.inputs = vec![Qubit::zero(); self.registers];
+        self.gates = gates;
+        self.wires = wires;
+        self.angle_wires = Vec::new();
+        self.operators.clear();
+        self.restart();
+    }
+
+    // Empties the circuit down to a single column on the given number of qubits
+    pub fn clear(&mut self, registers: usize) {
+        *self = Self { functions: std::mem::take(&mut self.functions), ..Self::new(registers) };
+    }
+
+    // Back to the inputs before the first column, keeping the circuit
+    pub fn restart(&mut self) {
+        self.system = QubitSystem::new(self.inputs.clone());
+        self.step = 0;
+        self.history.clear();
+        self.timings.clear();
+        self.classical = vec![None; self.registers];
+    }
+
+    // Starts each qubit in its own state instead of |0⟩, one per register, and restarts
+    pub fn set_inputs(&mut self, inputs: Vec<Qubit>) {
+        assert_eq!(inputs.len(), self.registers, "need one input state per qubit");
+        self.inputs = inputs;
+        self.restart();
+    }
+
+    pub fn inputs(&self) -> &[Qubit] {
+        &self.inputs
+    }
+
+    pub fn system(&self) -> &QubitSystem {
+        &self.system
+    }
+
+    pub fn get_values(&self) -> Vec<ComplexNumber> {
+        self.system.get_values()
+    }
+
+    // The latest outcome of each qubit, None for qubits that haven't been measured
+    pub fn classical_bits(&self) -> &[Option<usize>] {
+        &self.classical
+    }
+
+    // The state after each completed step, oldest first
+    pub fn history(&self) -> &[QubitSystem] {
+        &self.history
+    }
+
+    // Bloch vector of one qubit before the first column and after every column, found by
+    // replaying the whole circuit on a copy so this simulator's own step is left alone
+    // Meant for measurement-free circuits, measurements make each replay come out differently
+    pub fn bloch_trajectory(&self, qubit: usize) -> Vec<[f64; 3]> {
+        let mut replay = Simulator::from_circuit(self.gates.clone(), self.wires.clone());
+        replay.angle_wires = self.angle_wires.clone();
+        replay.functions = self.functions.clone();
+        replay.set_inputs(self.inputs.clone());
+
+        let mut trajectory = vec![replay.system.bloch_vector_for(qubit)];
+        while replay.step().is_some() {
+            trajectory.push(replay.system.bloch_vector_for(qubit));
+        }
+        trajectory
+    }
+
+    // (column, timing) of every step taken while profiling, oldest first
+    pub fn timings(&self) -> &[(usize, ColumnTiming)] {
+        &self.timings
+    }
+
+    pub fn gates_len(&self) -> usize {
+        self.gates.len()
+    }
+
+    pub fn registers_len(&self) -> usize {
+        self.registers
+    }
+
+    pub fn add_register(&mut self) {
+        self.operators.clear();
+        self.registers += 1;
+        self.classical.push(None);
+        self.inputs.push(Qubit::zero());
+        for column in self.gates.iter_mut() {
+            column.push(Gate::I);
+        }
+        self.system.add_qubit(Qubit::zero());
+    }
+
+    pub fn add_column(&mut self) {
+        self.gates.push(vec![Gate::I; self.registers]);
+    }
+
+    // Puts a gate in the circuit, marking the cells below it as continuations for
+    // multi-qubit gates. Returns false if there aren't enough qubits below for it
+    pub fn place_gate(&mut self, column: usize, register: usize, gate: Gate) -> bool {
+        let size = self.gate_size(&gate);
+        if size > self.registers - register {
+            return false;
+        }
+
+        for i in (register + 1)..self.gates[column].len() {
+            if self.gates[column][i] == Gate::Other(String::from("none")) {
+                self.gates[column][i] = Gate::I;
+            } else {
+                break;
+            }
+        }
+
+        self.gates[column][register] = gate;
+        self.invalidate(column);
+
+        for i in 1..size {
+            self.gates[column][register + i] = Gate::Other(String::from("none"));
+        }
+
+        if column == self.gates.len() - 1 {
+            self.add_column();
+        }
+        // handle replacing big gates with smaller
+        true
+    }
+
+    // Moves an already placed gate, along with its continuation cells, to a new cell
+    // Wires attached to the old cell are dropped. Returns false, leaving the circuit
+    // untouched, if the gate doesn't fit at the new position
+    pub fn move_gate(&mut self, from: (usize, usize), to: (usize, usize)) -> bool {
+        let gate = self.gates[from.0][from.1].clone();
+        let size = self.gate_size(&gate);
+        if from == to {
+            return true;
+        }
+        if size > self.registers - to.1 {
+            return false;
+        }
+
+        for i in 0..size {
+            self.gates[from.0][from.1 + i] = Gate::I;
+        }
+        self.invalidate(from.0);
+        self.wires.retain(|wire| wire.0 != from.0 || (wire.1 != from.1 && wire.2 != from.1));
+        self.angle_wires.retain(|wire| {
+            wire.column != from.0 || (wire.measured != from.1 && wire.target != from.1)
+        });
+
+        self.place_gate(to.0, to.1, gate)
+    }
+
+    // Gate matrices are memoized on their parameters (see Gate::cached_matrix),
+    // but the column's operator has to be rebuilt
+    pub fn edit_gate(&mut self, column: usize, register: usize, index: usize, value: f64) {
+        self.gates[column][register] = self.gates[column][register].with_param(index, value);
+        self.invalidate(column);
+    }
+
+    // Empties a cell, along with the continuation cells of a multi-qubit gate
+    pub fn remove_gate(&mut self, column: usize, register: usize) {
+        let size = self.gate_size(&self.gates[column][register]);
+        for i in 0..size {
+            self.gates[column][register + i] = Gate::I;
+        }
+        self.invalidate(column);
+    }
+
+    // Drops a column's cached operator so the next step rebuilds it
+    fn invalidate(&mut self, column: usize) {
+        if let Some(operator) = self.operators.get_mut(column) {
+            *operator = None;
+        }
+    }
+
+    // Whether stepping the column would reuse an already built operator
+    pub fn is_column_cached(&self, column: usize) -> bool {
+        self.operators.get(column).is_some_and(Option::is_some)
+    }
+
+    // Changes the ket an Assert gate expects, other gates are left alone
+    pub fn edit_assert(&mut self, column: usize, register: usize, ket: &str) {
+        if let Gate::Assert(expected) = &mut self.gates[column][register] {
+            *expected = ket.to_string();
+        }
+    }
+
+    // Flips an Init gate between setting |0⟩ and |1⟩, other gates are left alone
+    pub fn toggle_init(&mut self, column: usize, register: usize) {
+        if let Gate::Init(one) = &mut self.gates[column][register] {
+            *one = !*one;
+            self.invalidate(column);
+        }
+    }
+
+    pub fn gate_params(&self, column: usize, register: usize) -> Vec<f64> {
+        self.gates[column][register].params()
+    }
+
+    // (register, gate) for every real gate in a column, skipping identities and the
+    // continuation cells below multi-qubit gates
+    pub fn gates_in_step(&self, step: usize) -> Vec<(usize, Gate)> {
+        self.gates[step]
+            .iter()
+            .enumerate()
+            .filter(|(_, gate)| **gate != Gate::I && **gate != Gate::Other(String::from("none")))
+            .map(|(register, gate)| (register, gate.clone()))
+            .collect()
+    }
+
+    // Registers a named sub-circuit, placed in the circuit as Gate::Other(name)
+    // It runs on the qubits starting at the cell it's placed in
+    pub fn add_function(&mut self, name: &str, gates: Vec<Vec<Gate>>) {
+        self.functions.retain(|function| function.0 != name);
+        self.functions.push((name.to_string(), gates));
+    }
+
+    // How many cells a gate takes up in a column, registered functions cover every
+    // qubit they act on and everything else at least its own cell
+    pub fn gate_size(&self, gate: &Gate) -> usize {
+        let function = match gate {
+            Gate::Other(name) => self.function_width(name),
+            _ => None,
+        };
+        function.unwrap_or(gate.num_qubits()).max(1)
+    }
+
+    pub fn functions(&self) -> &[(String, Vec<Vec<Gate>>)] {
+        &self.functions
+    }
+
+    // Whether the circuit uses the named function, directly or inside another saved gate
+    pub fn uses_function(&self, gates: &[Vec<Gate>], name: &str) -> bool {
+        let mut seen: Vec<&str> = Vec::new();
+        let mut pending = vec![gates];
+
+        while let Some(gates) = pending.pop() {
+            for gate in gates.iter().flatten() {
+                let Gate::Other(inner) = gate else {
+                    continue;
+                };
+                if inner == name {
+                    return true;
+                }
+                if seen.contains(&inner.as_str()) {
+                    continue;
+                }
+                seen.push(inner);
+                if let Some((_, columns)) = self.functions.iter().find(|f| f.0 == *inner) {
+                    pending.push(columns);
+                }
+            }
+        }
+        false
+    }
+
+    // How many qubits a registered function spans, None if there's no such function
+    pub fn function_width(&self, name: &str) -> Option<usize> {
+        let (_, columns) = self.functions.iter().find(|function| function.0 == name)?;
+        Some(columns.iter().map(Vec::len).max().unwrap_or(0))
+    }
+
+    // Whether there are columns left to step through
+    pub fn has_next_step(&self) -> bool {
+        self.step < self.gates.len()
+    }
+
+    // Runs the next column and records the resulting state
+    // Returns None if the circuit has already finished
+    pub fn step(&mut self) -> Option<StepOutcome> {
+        if !self.has_next_step() {
+            return None;
+        }
+        self.step += 1;
+        let mut gates = self.gates[self.step - 1].clone();
+        let wires: Vec<(usize, usize, usize)> =
+            self.wires.iter().filter(|wire| wire.0 == self.step - 1).copied().collect();
+        let angle_wires: Vec<AngleWire> = self
+            .angle_wires
+            .iter()
+            .filter(|wire| wire.column == self.step - 1)
+            .cloned()
+            .collect();
+
+        let mut assertions = Vec::new();
+        for (i, gate) in gates.iter_mut().enumerate() {
+            if let Gate::Assert(ket) = gate {
+                assertions.push((i, self.system.matches_ket(ket)));
+                *gate = Gate::I;
+            }
+        }
+
+        let mut skipped_functions = Vec::new();
+        let mut function_measurements = Vec::new();
+        for i in 0..gates.len() {
+            let Gate::Other(name) = &gates[i] else { continue };
+            if name == "none" {
+                continue;
+            }
+            let name = name.clone();
+            if !self.apply_function(i, &name, 0, &mut function_measurements) {
+                skipped_functions.push((i, name.clone()));
+            }
+
+            // Its continuation cells go as well, column_operator would leave them out
+            let end = (i + self.function_width(&name).unwrap_or(1)).clamp(i + 1, gates.len());
+            gates[i..end].fill(Gate::I);
+        }
+        // Wires later in the column can already read what the saved gates measured
+        for (qubit, outcome, _) in &function_measurements {
+            self.classical[*qubit] = Some(*outcome);
+        }
+
+        // The operator depends on the outcomes, so columns with wires are never cached
+        let classical_column = !wires.is_empty() || !angle_wires.is_empty();
+        let (wires, angle_wires) =
+            apply_stored_wires(&mut gates, &wires, &angle_wires, &self.classical);
+
+        let column_measurements = if self.profiling {
+            let (measurements, timing) =
+                step_column_profiled(&mut self.system, &gates, &wires, &angle_wires);
+            tracing::debug!(
+                "column {} built in {:?}, applied in {:?}",
+                self.step - 1,
+                timing.build,
+                timing.apply
+            );
+            self.timings.push((self.step - 1, timing));
+            measurements
+        } else if !classical_column && gates.iter().all(Gate::is_identity) {
+            Vec::new()
+        } else if !classical_column
+            && !gates.iter().any(|gate| matches!(gate, Gate::M | Gate::MeasureReset))
+        {
+            let column = self.step - 1;
+            self.operators.resize(self.gates.len().max(self.operators.len()), None);
+            let operator = self.operators[column]
+                .get_or_insert_with(|| Rc::new(QubitSystem::column_operator(&gates)))
+                .clone();
+            self.system.apply_column_operator(&operator, &gates);
+            Vec::new()
+        } else {
+            step_column_adaptive(&mut self.system, &gates, &wires, &angle_wires)
+        };
+        let mut measurements = function_measurements;
+        measurements.extend(column_measurements);
+        for (qubit, outcome, _) in &measurements {
+            self.classical[*qubit] = Some(*outcome);
+        }
+        self.history.push(self.system.clone());
+        Some(StepOutcome { column: self.step - 1, measurements, assertions, skipped_functions })
+    }
+
+    // Runs every column of a registered function on the qubits from index down, along with
+    // any functions inside it. Unknown names do nothing, and a function too tall to fit or
+    // nested deeper than MAX_FUNCTION_DEPTH isn't run, returning false
+    // Measurements inside it are added to measurements like a column's own
+    fn apply_function(
+        &mut self,
+        index: usize,
+        name: &str,
+        depth: usize,
+        measurements: &mut Vec<(usize, usize, f64)>,
+    ) -> bool {
+        let Some((_, columns)) = self.functions.iter().find(|function| function.0 == name) else {
+            return true;
+        };
+        if depth > MAX_FUNCTION_DEPTH
+            || self.function_width(name).is_some_and(|width| index + width > self.registers_len())
+        {
+            return false;
+        }
+
+        let mut applied = true;
+        for column in columns.clone() {
+            for (offset, gate) in column.iter().enumerate() {
+                if let Gate::Other(inner) = gate
+                    && inner != "none"
+                {
+                    applied &= self.apply_function(index + offset, inner, depth + 1, measurements);
+                    continue;
+                }
+                if let Gate::M | Gate::MeasureReset = gate {
+                    let qubit = index + offset;
+                    let (outcome, probability) = self.system.measure_with_probability(qubit);
+                    if outcome == 1 && *gate == Gate::MeasureReset {
+                        self.system.apply_single(qubit, &Matrix::pauli_x());
+                    }
+                    measurements.push((qubit, outcome, probability));
+                    continue;
+                }
+                let targets: Vec<usize> =
+                    (index + offset..index + offset + gate.num_qubits()).collect();
+                self.system.apply(gate, &targets);
+            }
+        }
+        applied
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::run_circuit;
+    use crate::examples::examples;
+    use std::f64::consts::FRAC_PI_2;
+
+    fn teleportation() -> Simulator {
+        let (_, gates, wires, _) =
+            examples().into_iter().find(|example| example.0 == "Quantum Teleportation").unwrap();
+        Simulator::from_circuit(gates, wires)
+    }
+
+    #[test]
+    pub fn teleport_steps() {
+        for _ in 0..10 {
+            let mut simulator = teleportation();
+
+            let mut steps = 0;
+            while simulator.step().is_some() {
+                steps += 1;
+            }
+            assert_eq!(steps, 7);
+            assert_eq!(simulator.history().len(), 7);
+            assert!(!simulator.has_next_step());
+
+            // The example teleports |1⟩ from the first qubit onto the last
+            assert!((simulator.system().outcome_probability(2, 1) - 1.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    pub fn measurement_outcomes() {
+        let mut simulator = Simulator::from_circuit(
+            vec![vec![Gate::X, Gate::H], vec![Gate::M, Gate::M], vec![Gate::I, Gate::I]],
+            Vec::new(),
+        );
+
+        let first = simulator.step().unwrap();
+        assert_eq!(
+            first,
+            StepOutcome {
+                column: 0,
+                measurements: Vec::new(),
+                assertions: Vec::new(),
+                skipped_functions: Vec::new()
+            }
+        );
+
+        let second = simulator.step().unwrap();
+        assert_eq!(second.column, 1);
+        assert_eq!(second.measurements.len(), 2);
+        assert_eq!(second.measurements[0].0, 0);
+        assert_eq!(second.measurements[0].1, 1);
+        assert!((second.measurements[0].2 - 1.0).abs() < 1e-9);
+
+        let (qubit, outcome, probability) = second.measurements[1];
+        assert_eq!(qubit, 1);
+        assert!((probability - 0.5).abs() < 1e-9);
+        assert!((simulator.system().outcome_probability(1, outcome) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    pub fn wire_measurements() {
+        let mut simulator = teleportation();
+        for _ in 0..4 {
+            simulator.step();
+        }
+
+        // The wire reads the same measurement as the M gate rather than measuring again
+        let outcome = simulator.step().unwrap();
+        assert_eq!(outcome.measurements.len(), 1);
+        assert_eq!(outcome.measurements[0].0, 1);
+        assert!((outcome.measurements[0].2 - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    pub fn adaptive_angle() {
+        for (first, expected_angle) in [(Gate::X, FRAC_PI_2), (Gate::I, 0.3)] {
+            let mut simulator = Simulator::from_circuit(
+                vec![vec![first, Gate::H], vec![Gate::M, Gate::RZ(0.0)], vec![Gate::I, Gate::I]],
+                Vec::new(),
+            );
+            simulator.angle_wires.push(AngleWire {
+                column: 1,
+                measured: 0,
+                target: 1,
+                angles: [0.3, FRAC_PI_2],
+            });
+            simulator.step();
+            simulator.step();
+
+            let mut expected = QubitSystem::new(vec![Qubit::zero(), Qubit::zero().hadamard()]);
+            expected.apply_gate(1, Gate::RZ(expected_angle).to_matrix());
+            if simulator.system().outcome_probability(0, 1) > 0.5 {
+                expected.apply_gate(0, Gate::X.to_matrix());
+            }
+            assert!((simulator.system().fidelity(&expected) - 1.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    pub fn assertions() {
+        let mut simulator = Simulator::from_circuit(
+            vec![
+                vec![Gate::X, Gate::I],
+                vec![Gate::Assert(String::from("|10⟩")), Gate::H],
+                vec![Gate::I, Gate::Assert(String::from("10"))],
+            ],
+            Vec::new(),
+        );
+
+        simulator.step();
+        assert_eq!(simulator.step().unwrap().assertions, vec![(0, true)]);
+        // The H has put qubit 1 into a superposition, so it's no longer |10⟩
+        assert_eq!(simulator.step().unwrap().assertions, vec![(1, false)]);
+        assert!((simulator.system().outcome_probability(1, 0) - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    pub fn step_gates() {
+        let simulator = teleportation();
+        assert_eq!(simulator.gates_in_step(4), vec![(1, Gate::M), (2, Gate::X)]);
+        assert_eq!(simulator.gates_in_step(2), vec![(0, Gate::CNOT)]);
+        assert!(simulator.gates_in_step(6).is_empty());
+    }
+
+    #[test]
+    pub fn empty_circuits() {
+        for gates in [Vec::new(), vec![Vec::new()]] {
+            let mut simulator = Simulator::from_circuit(gates, vec![(0, 1, 2)]);
+            assert_eq!(simulator.registers_len(), 1);
+            assert!(simulator.wires.is_empty());
+            assert!(simulator.step().is_some());
+            assert_eq!(simulator.system().to_string(), "1.00 |0⟩");
+        }
+    }
+
+    #[test]
+    pub fn restart_history() {
+        let mut simulator = teleportation();
+        simulator.step();
+        simulator.step();
+        assert_eq!(simulator.step, 2);
+        assert_eq!(simulator.history()[1].to_string(), "1/√2 |100⟩, 1/√2 |111⟩");
+
+        simulator.restart();
+        assert_eq!(simulator.step, 0);
+        assert!(simulator.history().is_empty());
+        assert_eq!(simulator.get_values()[0], ComplexNumber::new(1.0, 0.0));
+    }
+
+    #[test]
+    pub fn profiled_steps() {
+        let mut simulator = teleportation();
+        simulator.step();
+        assert!(simulator.timings().is_empty());
+
+        simulator.profiling = true;
+        simulator.step();
+        simulator.step();
+        let columns: Vec<usize> = simulator.timings().iter().map(|(column, _)| *column).collect();
+        assert_eq!(columns, vec![1, 2]);
+        // Profiling only times the steps, the state ends up the same as without it
+        assert_eq!(simulator.history()[1].to_string(), "1/√2 |100⟩, 1/√2 |111⟩");
+
+        simulator.restart();
+        assert!(simulator.timings().is_empty());
+    }
+
+    #[test]
+    pub fn cached_operators() {
+        let mut simulator = Simulator::from_circuit(
+            vec![vec![Gate::H, Gate::I], vec![Gate::RX(0.3), Gate::X], vec![Gate::Z, Gate::Y]],
+            Vec::new(),
+        );
+        while simulator.step().is_some() {}
+        let first_run = simulator.get_values();
+        assert!((0..3).all(|column| simulator.is_column_cached(column)));
+
+        // Replaying reuses every operator and lands on the same state
+        simulator.restart();
+        while simulator.step().is_some() {}
+        assert_eq!(simulator.get_values(), first_run);
+
+        simulator.edit_gate(1, 0, 0, 0.9);
+        assert!(simulator.is_column_cached(0));
+        assert!(!simulator.is_column_cached(1));
+        assert!(simulator.is_column_cached(2));
+
+        simulator.restart();
+        while simulator.step().is_some() {}
+        let mut expected = QubitSystem::new(vec![Qubit::zero(); 2]);
+        expected.apply_gates(vec![Gate::H, Gate::I]);
+        expected.apply_gates(vec![Gate::RX(0.9), Gate::X]);
+        expected.apply_gates(vec![Gate::Z, Gate::Y]);
+        assert!(simulator.system().fidelity(&expected) > 1.0 - 1e-9);
+    }
+
+    #[test]
+    pub fn edit_params() {
+        let gates = vec![vec![Gate::U3(0.1, 0.2, 0.3)]];
+        let mut simulator = Simulator::from_circuit(gates, Vec::new());
+
+        simulator.edit_gate(0, 0, 1, 0.8);
+        assert_eq!(simulator.gate_params(0, 0), vec![0.1, 0.8, 0.3]);
+        assert_eq!(simulator.gates[0][0], Gate::U3(0.1, 0.8, 0.3));
+
+        simulator.toggle_init(0, 0);
+        assert_eq!(simulator.gates[0][0], Gate::U3(0.1, 0.8, 0.3));
+        simulator.gates[0][0] = Gate::Init(false);
+        simulator.toggle_init(0, 0);
+        assert_eq!(simulator.gates[0][0], Gate::Init(true));
+    }
+
+    #[test]
+    pub fn hadamard_trajectory() {
+        let gates = vec![vec![Gate::I, Gate::H], vec![Gate::I; 2]];
+        let mut simulator = Simulator::from_circuit(gates, Vec::new());
+        simulator.step();
+
+        let trajectory = simulator.bloch_trajectory(1);
+        assert_eq!(trajectory.len(), 3);
+        assert_eq!(trajectory[0], [0.0, 0.0, 1.0]);
+        assert!((trajectory[1][0] - 1.0).abs() < 1e-9 && trajectory[1][2].abs() < 1e-9);
+        assert_eq!(trajectory[1], trajectory[2]);
+        let untouched = simulator.bloch_trajectory(0);
+        assert!(untouched.iter().all(|point| (point[2] - 1.0).abs() < 1e-9));
+        assert_eq!(simulator.step, 1);
+    }
+
+    #[test]
+    pub fn later_column_wires() {
+        // Teleportation with the corrections moved a column or two after the measurements
+        let none = || Gate::Other(String::from("none"));
+        let gates = vec![
+            vec![Gate::X, Gate::H, Gate::I],
+            vec![Gate::I, Gate::CNOT, none()],
+            vec![Gate::CNOT, none(), Gate::I],
+            vec![Gate::H, Gate::I, Gate::I],
+            vec![Gate::M, Gate::M, Gate::I],
+            vec![Gate::I, Gate::I, Gate::X],
+            vec![Gate::I, Gate::I, Gate::Z],
+            vec![Gate::I, Gate::I, Gate::I],
+        ];
+
+        for _ in 0..20 {
+            let mut simulator = Simulator::from_circuit(gates.clone(), vec![(5, 1, 2), (6, 0, 2)]);
+            let mut outcomes = Vec::new();
+            while let Some(outcome) = simulator.step() {
+                outcomes.push(outcome);
+            }
+
+            // Only column 4 measures anything, the wires reuse its stored outcomes
+            assert_eq!(outcomes[4].measurements.len(), 2);
+            assert!(outcomes[5].measurements.is_empty() && outcomes[6].measurements.is_empty());
+            let bits = simulator.classical_bits();
+            assert!(bits[0].is_some() && bits[1].is_some() && bits[2].is_none());
+            assert!((simulator.system().outcome_probability(2, 1) - 1.0).abs() < 1e-9);
+        }
+
+        let mut system = run_circuit(&gates, &[(5, 1, 2), (6, 0, 2)]);
+        assert_eq!(system.measure_single(2), 1);
+    }
+
+    #[test]
+    pub fn next_step() {
+        let mut simulator = Simulator::new(1);
+        simulator.add_column();
+        assert!(simulator.has_next_step());
+
+        simulator.step = 1;
+        assert!(simulator.has_next_step());
+
+        simulator.step = 2;
+        assert!(!simulator.has_next_step());
+    }
+
+    #[test]
+    pub fn move_cnot() {
+        let mut simulator = Simulator::new(3);
+        simulator.place_gate(0, 0, Gate::CNOT);
+
+        assert!(simulator.move_gate((0, 0), (1, 1)));
+        assert_eq!(simulator.gates[0], vec![Gate::I, Gate::I, Gate::I]);
+        assert_eq!(
+            simulator.gates[1],
+            vec![Gate::I, Gate::CNOT, Gate::Other(String::from("none"))]
+        );
+
+        // Doesn't fit on the last qubit, so it stays where it is
+        assert!(!simulator.move_gate((1, 1), (0, 2)));
+        assert_eq!(simulator.gates[1][1], Gate::CNOT);
+        assert_eq!(simulator.gates[1][2], Gate::Other(String::from("none")));
+    }
+
+    #[test]
+    pub fn function_expansion() {
+        let mut simulator = Simulator::new(2);
+        simulator.add_function("bell", vec![vec![Gate::H, Gate::I], vec![Gate::CNOT]]);
+        simulator.place_gate(0, 0, Gate::Other(String::from("bell")));
+
+        assert!(simulator.step().is_some());
+        assert_eq!(simulator.system().to_string(), "1/√2 |00⟩, 1/√2 |11⟩");
+    }
+
+    #[test]
+    pub fn nested_functions() {
+        let mut simulator = Simulator::new(2);
+        simulator.add_function("inner", vec![vec![Gate::X]]);
+        simulator.add_function("outer", vec![vec![Gate::I, Gate::Other(String::from("inner"))]]);
+        simulator.place_gate(0, 0, Gate::Other(String::from("outer")));
+        assert!(simulator.uses_function(&simulator.gates, "inner"));
+        assert!(!simulator.uses_function(&simulator.gates, "other"));
+
+        assert!(simulator.step().unwrap().skipped_functions.is_empty());
+        assert_eq!(simulator.system().to_string(), "1.00 |01⟩");
+
+        // A function using itself stops once it's nested too deeply
+        simulator.add_function("loop", vec![vec![Gate::Other(String::from("loop"))]]);
+        simulator.place_gate(1, 0, Gate::Other(String::from("loop")));
+        let outcome = simulator.step().unwrap();
+        assert_eq!(outcome.skipped_functions, vec![(0, String::from("loop"))]);
+    }
+
+    #[test]
+    pub fn unfit_functions() {
+        // Placed before it was registered, so it wasn't known to be two qubits tall
+        let mut simulator = Simulator::new(2);
+        assert!(simulator.place_gate(0, 1, Gate::Other(String::from("bell"))));
+        assert!(simulator.place_gate(1, 0, Gate::Other(String::from("missing"))));
+
+        simulator.add_function("bell", vec![vec![Gate::H, Gate::I], vec![Gate::CNOT]]);
+        assert_eq!(simulator.function_width("bell"), Some(2));
+        assert_eq!(simulator.function_width("missing"), None);
+        assert!(!simulator.place_gate(1, 1, Gate::Other(String::from("bell"))));
+
+        let outcome = simulator.step().unwrap();
+        assert_eq!(outcome.skipped_functions, vec![(1, String::from("bell"))]);
+        let outcome = simulator.step().unwrap();
+        assert!(outcome.skipped_functions.is_empty());
+        assert_eq!(simulator.system().to_string(), "1.00 |00⟩");
+    }
+
+    #[test]
+    pub fn measuring_functions() {
+        // Wires read what a saved gate measured, both in its own column and the next
+        let gates = vec![vec![Gate::Other(String::from("flip")), Gate::X], vec![Gate::I, Gate::X]];
+        let mut simulator = Simulator::from_circuit(gates, vec![(0, 0, 1), (1, 0, 1)]);
+        simulator.add_function("flip", vec![vec![Gate::X], vec![Gate::MeasureReset]]);
+
+        let outcome = simulator.step().unwrap();
+        assert_eq!(outcome.measurements.len(), 1);
+        assert_eq!(outcome.measurements[0].0, 0);
+        assert_eq!(outcome.measurements[0].1, 1);
+        assert_eq!(simulator.system().to_string(), "1.00 |01⟩");
+
+        simulator.step();
+        assert_eq!(simulator.system().to_string(), "1.00 |00⟩");
+    }
+}
@@ -0,0 +1,119 @@
+use rand::{SeedableRng, rngs::StdRng};
+use std::f64::consts::{FRAC_PI_8, PI};
+
+use quantum::{
+    circuit::{run_circuit, run_circuit_on},
+    examples::{Example, examples, gate_note, phase_estimation_example, verify_teleportation},
+    prelude::*,
+};
+
+fn example(name: &str) -> Example {
+    examples().into_iter().find(|example| example.0 == name).unwrap()
+}
+
+fn probabilities(system: &QubitSystem) -> Vec<f64> {
+    system.get_values().iter().map(|n| n.abs_squared()).collect()
+}
+
+#[test]
+fn entanglement() {
+    let (_, gates, wires, _) = example("Entanglement");
+    let system = run_circuit(&gates, &wires);
+
+    let half = ComplexNumber::SQRT_HALF;
+    assert_eq!(system.get_values(), vec![half, c!(0.0), c!(0.0), half]);
+}
+
+#[test]
+fn partial_entanglement() {
+    let (_, gates, wires, _) = example("Partial Entanglement");
+    let system = run_circuit(&gates, &wires);
+    let probabilities = probabilities(&system);
+
+    assert!(system.system_normal());
+    assert!(probabilities[2] < 1e-9);
+    assert!((probabilities[1] - FRAC_PI_8.sin().powi(2) / 2.0).abs() < 1e-6);
+}
+
+#[test]
+fn partial_entanglement_notes() {
+    let (_, gates, _, notes) = example("Partial Entanglement");
+
+    assert_eq!(gates[1][1], Gate::RY(FRAC_PI_8));
+    assert_eq!(gate_note(&notes, 1, 1), Some("π/8"));
+    assert_eq!(gate_note(&notes, 1, 0), None);
+}
+
+#[test]
+fn teleportation() {
+    let (_, mut gates, wires, _) = example("Quantum Teleportation");
+
+    // The example teleports |1⟩, swap in a source with an arbitrary phase as well
+    gates[0][0] = Gate::P(0.6);
+    gates.insert(0, vec![Gate::RX(1.1), Gate::I, Gate::I]);
+    let wires: Vec<_> = wires.iter().map(|(column, from, to)| (column + 1, *from, *to)).collect();
+    let source = Qubit::zero().dot_matrix(Matrix::rx(1.1)).dot_matrix(Matrix::phase(0.6)).as_vec();
+
+    // Seeded so the same measurements come out every run, and all four corrections get checked
+    let mut corrections = [false; 4];
+    for seed in 0..20 {
+        let mut system = QubitSystem::new_seeded(vec![Qubit::zero(); 3], seed);
+        run_circuit_on(&mut system, &gates, &wires);
+
+        // The first two qubits have been measured, so the third can be read off directly
+        let base = (system.measure_single(0) << 2) | (system.measure_single(1) << 1);
+        let values = system.get_values();
+        let overlap = source[0].conjugate() * values[base] + source[1].conjugate() * values[base + 1];
+
+        assert!((overlap.abs_squared() - 1.0).abs() < 1e-9);
+        corrections[base >> 1] = true;
+    }
+    assert_eq!(corrections, [true; 4]);
+}
+
+#[test]
+fn random_teleportation() {
+    let mut rng = StdRng::seed_from_u64(1420);
+
+    for _ in 0..50 {
+        let source = Qubit::random(&mut rng);
+        assert!(source.is_normal());
+        assert!(verify_teleportation(source));
+    }
+}
+
+#[test]
+fn deutsch_jozsa() {
+    let (_, gates, wires, _) = example("Deutsch–Jozsa Algorithm");
+
+    for _ in 0..5 {
+        let system = run_circuit(&gates, &wires);
+        let probabilities = probabilities(&system);
+
+        // The oracle is balanced, so the first two qubits always read 10 rather than 00
+        assert!((probabilities[4] + probabilities[5] - 1.0).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn parity() {
+    let (_, gates, wires, _) = example("Parity");
+    let mut system = run_circuit(&gates, &wires);
+
+    assert_eq!(system.measure(), vec![1, 0, 1, 0]);
+}
+
+#[test]
+fn phase_estimation() {
+    let (_, gates, wires, _) = example("Phase Estimation");
+
+    // π/2 is a quarter turn, 0.01 in binary, so the 3 bit register reads 010
+    for _ in 0..5 {
+        let mut system = run_circuit(&gates, &wires);
+        assert_eq!(system.measure_register(&[0, 1, 2]), 2);
+    }
+
+    // 7π/4 is 0.111 of a turn, read by a 4 bit register as 1110
+    let gates = phase_estimation_example(7.0 * PI / 4.0, 4);
+    assert_eq!(run_circuit(&gates, &[]).measure_register(&[0, 1, 2, 3]), 14);
+}